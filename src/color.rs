@@ -0,0 +1,151 @@
+//! Colorized `Dump` output for terminals.
+
+use core::fmt;
+#[cfg(not(feature = "no_std"))]
+use std::io::IsTerminal;
+
+use tracing::{Link, Site};
+use {Dump, Snarc};
+
+/// ANSI escape sequence for strong reference markers.
+const STRONG: &str = "\x1b[32m";
+/// ANSI escape sequence for weak reference markers.
+const WEAK: &str = "\x1b[36m";
+/// ANSI escape sequence for links with an unknown site.
+const UNKNOWN: &str = "\x1b[31m";
+/// ANSI escape sequence resetting all attributes.
+const RESET: &str = "\x1b[0m";
+
+/// Output helper like `Dump`, highlighting its output with ANSI colors.
+///
+/// The `S|` markers of strong references are rendered green, the `W|` markers of weak ones cyan.
+/// Links of an origin chain whose site is `Site::Unknown` are rendered red, drawing attention to
+/// references created without a location or annotation. Example:
+///
+/// ```rust
+/// use snarc::{DumpColored, Snarc};
+///
+/// let foo = Snarc::new(123);
+/// let bar = foo.clone();
+///
+/// println!("{}", DumpColored::new(&bar));
+/// ```
+///
+/// Use `DumpColored::for_terminal` to fall back to plain output if the destination is not a
+/// terminal.
+#[derive(Debug)]
+pub struct DumpColored<'a, T: ?Sized + 'a> {
+    /// The reference whose family is dumped.
+    this: &'a Snarc<T>,
+    /// Whether to emit colors at all, otherwise the output is the same as `Dump`.
+    enabled: bool,
+}
+
+impl<'a, T: ?Sized + 'a> DumpColored<'a, T> {
+    /// Creates a new helper that always emits colors.
+    pub fn new(this: &'a Snarc<T>) -> DumpColored<'a, T> {
+        DumpColored {
+            this,
+            enabled: true,
+        }
+    }
+
+    /// Creates a new helper that only emits colors if `stream` is a terminal, and plain `Dump`
+    /// output otherwise, e.g. if it is redirected to a file.
+    #[cfg(not(feature = "no_std"))]
+    pub fn for_terminal<S: IsTerminal>(this: &'a Snarc<T>, stream: &S) -> DumpColored<'a, T> {
+        DumpColored {
+            this,
+            enabled: stream.is_terminal(),
+        }
+    }
+}
+
+impl<'a, T: ?Sized + 'a> fmt::Display for DumpColored<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            return fmt::Display::fmt(&Dump(self.this), f);
+        }
+
+        writeln!(f, "Family associated with ID: {}", Snarc::id(self.this))?;
+
+        let (mut strongs, mut weaks) = Snarc::family(self.this);
+        strongs.sort();
+        weaks.sort();
+
+        for (marker, color, origins) in [("S", STRONG, strongs), ("W", WEAK, weaks)] {
+            for origin in origins {
+                write!(f, "{}{}|{} ", color, marker, RESET)?;
+                for (idx, link) in origin.ancestors().enumerate() {
+                    if idx > 0 {
+                        write!(f, " <- ")?;
+                    }
+                    if link.site == Site::Unknown {
+                        write!(f, "{}{}{}", UNKNOWN, Link(link), RESET)?;
+                    } else {
+                        write!(f, "{}", Link(link))?;
+                    }
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DumpColored;
+    #[cfg(not(feature = "no_std"))]
+    use std::fs::File;
+    #[cfg(not(feature = "no_std"))]
+    use Dump;
+    use Snarc;
+
+    #[test]
+    fn colors() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let t = s.clone_annotated("t");
+        let w = Snarc::downgrade(&t);
+        let output = DumpColored::new(&s).to_string();
+
+        let u = Snarc::from(2);
+        let v = u.clone_at_line("foo.rs", 2);
+        let unknown = DumpColored::new(&v).to_string();
+
+        #[cfg(feature = "tracking")]
+        {
+            assert!(output.contains("\x1b[32mS|\x1b[0m clone<1>[\"t\"]"));
+            assert!(output.contains("\x1b[36mW|\x1b[0m downgrade<2>"));
+            assert!(unknown.contains("\x1b[32mS|\x1b[0m \x1b[31mnew<0>[?]"));
+            assert!(unknown.contains(" <- \x1b[31mnew<0>[?]"));
+        }
+        #[cfg(not(feature = "tracking"))]
+        assert_eq!(
+            (output.as_str(), unknown.as_str()),
+            (
+                "Family associated with ID: 0\n",
+                "Family associated with ID: 0\n"
+            )
+        );
+
+        drop((t, w));
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn plain_unless_terminal() {
+        let name = format!("snarc-dump-colored-test-{}", ::std::process::id());
+        let path = ::std::env::temp_dir().join(name);
+        let file = File::create(&path).unwrap();
+
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let output = DumpColored::for_terminal(&t, &file).to_string();
+
+        drop(file);
+        ::std::fs::remove_file(path).unwrap();
+        assert_eq!(output, Dump(&t).to_string());
+    }
+}
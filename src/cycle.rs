@@ -0,0 +1,218 @@
+//! Reference cycle detection.
+//!
+//! Values that know about the `Snarc`s they contain can implement `TracksChildren`, allowing
+//! `detect_cycle` to walk the graph of values reachable from a reference and report the first
+//! cycle found. Only children of the same type are supported.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+
+use tracing::Origin;
+use Snarc;
+
+/// A value containing references to other values of the same type.
+///
+/// Implemented by users to allow `detect_cycle` to walk the reference graph. Example:
+///
+/// ```rust
+/// use std::sync::Mutex;
+/// use snarc::{detect_cycle, Snarc, TracksChildren};
+///
+/// struct Node {
+///     children: Mutex<Vec<Snarc<Node>>>,
+/// }
+///
+/// impl TracksChildren for Node {
+///     fn children(&self, visit: &mut dyn FnMut(&Snarc<Node>)) {
+///         self.children.lock().unwrap().iter().for_each(visit)
+///     }
+/// }
+///
+/// let a = Snarc::new(Node { children: Mutex::new(Vec::new()) });
+/// let b = Snarc::new(Node { children: Mutex::new(vec![a.clone()]) });
+/// a.children.lock().unwrap().push(b.clone());
+///
+/// assert_eq!(detect_cycle(&a).map(|cycle| cycle.len()), Some(2));
+/// # a.children.lock().unwrap().clear();
+/// ```
+pub trait TracksChildren {
+    /// Calls `visit` with every `Snarc` contained in this value.
+    ///
+    /// `visit` does not access the values the children point to, it is safe to call while holding a
+    /// lock on `self`.
+    fn children(&self, visit: &mut dyn FnMut(&Snarc<Self>));
+}
+
+/// Returns the address of the value `this` points to, identifying it while it is alive.
+fn address<T: ?Sized>(this: &Snarc<T>) -> usize {
+    Snarc::as_ptr(this) as *const u8 as usize
+}
+
+/// Walks all values reachable from `root` and returns the first reference cycle found.
+///
+/// The cycle is returned as the origins of the references it consists of, in order: the first
+/// reference points to a value containing the second one, and so on, until the last one points
+/// back to the value containing the first. Values that are reachable from `root`, but not part of
+/// the cycle, are not included.
+///
+/// While walking, each child is briefly cloned, which creates a short-lived reference in its
+/// family.
+pub fn detect_cycle<T: TracksChildren + ?Sized>(root: &Snarc<T>) -> Option<Vec<Origin>> {
+    /// A value currently being walked.
+    struct Frame<T: ?Sized> {
+        /// Address of the value.
+        address: usize,
+        /// Children remaining to be walked, along with the origin of the contained reference.
+        children: Vec<(Origin, Snarc<T>)>,
+    }
+
+    /// Collects the children of `node` as a frame.
+    fn enter<T: TracksChildren + ?Sized>(node: &Snarc<T>) -> Frame<T> {
+        let mut children = Vec::new();
+        node.children(&mut |child| children.push((Snarc::origin(child), child.clone())));
+        // Walk children in their original order.
+        children.reverse();
+
+        Frame {
+            address: address(node),
+            children,
+        }
+    }
+
+    // Values on the current path, mapped to their position in `path`.
+    let mut on_path: BTreeMap<usize, usize> = BTreeMap::new();
+    // Values whose children have been walked completely.
+    let mut done: BTreeSet<usize> = BTreeSet::new();
+    // Origins of the references leading to each frame but the first.
+    let mut edges: Vec<Origin> = Vec::new();
+    let mut path = Vec::new();
+    path.push(enter(root));
+    on_path.insert(address(root), 0);
+
+    while let Some(frame) = path.last_mut() {
+        match frame.children.pop() {
+            Some((origin, child)) => {
+                let child_address = address(&child);
+
+                if let Some(&start) = on_path.get(&child_address) {
+                    // `edges[start..]` leads from the value at `start` to the current one.
+                    let mut cycle = edges.split_off(start);
+                    cycle.push(origin);
+                    return Some(cycle);
+                }
+
+                if !done.contains(&child_address) {
+                    on_path.insert(child_address, path.len());
+                    edges.push(origin);
+                    path.push(enter(&child));
+                }
+            }
+            None => {
+                let address = frame.address;
+                path.pop();
+                edges.pop();
+                on_path.remove(&address);
+                done.insert(address);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_cycle, TracksChildren};
+    use std::sync::Mutex;
+    use Snarc;
+
+    /// Graph node with an arbitrary number of children.
+    struct Node {
+        children: Mutex<Vec<Snarc<Node>>>,
+    }
+
+    impl Node {
+        fn new(children: Vec<Snarc<Node>>) -> Snarc<Node> {
+            Snarc::new(Node {
+                children: Mutex::new(children),
+            })
+        }
+
+        fn clear(&self) {
+            self.children.lock().unwrap().clear();
+        }
+    }
+
+    impl TracksChildren for Node {
+        fn children(&self, visit: &mut dyn FnMut(&Snarc<Node>)) {
+            self.children.lock().unwrap().iter().for_each(visit)
+        }
+    }
+
+    #[test]
+    fn no_cycle() {
+        // Diamond: a -> (b, c) -> d.
+        let d = Node::new(Vec::new());
+        let b = Node::new(vec![d.clone()]);
+        let c = Node::new(vec![d.clone()]);
+        let a = Node::new(vec![b.clone(), c.clone()]);
+
+        assert!(detect_cycle(&a).is_none());
+        assert!(detect_cycle(&d).is_none());
+    }
+
+    #[test]
+    fn two_node_cycle() {
+        let a = Node::new(Vec::new());
+        let b = Node::new(vec![a.clone_at_line("foo.rs", 1)]);
+        a.children
+            .lock()
+            .unwrap()
+            .push(b.clone_at_line("foo.rs", 2));
+
+        let cycle = detect_cycle(&a).expect("No cycle reported");
+        assert_eq!(cycle.len(), 2);
+        #[cfg(feature = "tracking")]
+        {
+            use tracing::Site;
+
+            let sites: Vec<_> = cycle.iter().map(|origin| origin.site.clone()).collect();
+            assert_eq!(
+                sites,
+                vec![
+                    Site::SourceFile {
+                        file: "foo.rs",
+                        line: 2,
+                        column: None,
+                    },
+                    Site::SourceFile {
+                        file: "foo.rs",
+                        line: 1,
+                        column: None,
+                    },
+                ]
+            );
+        }
+
+        // The temporary clones made while walking are gone again.
+        assert_eq!(Snarc::strong_count(&a), 2);
+        assert_eq!(Snarc::strong_count(&b), 2);
+
+        a.clear();
+        assert!(detect_cycle(&a).is_none());
+    }
+
+    #[test]
+    fn cycle_not_through_root() {
+        // root -> a -> b -> c -> a
+        let a = Node::new(Vec::new());
+        let c = Node::new(vec![a.clone()]);
+        let b = Node::new(vec![c.clone()]);
+        a.children.lock().unwrap().push(b.clone());
+        let root = Node::new(vec![a.clone()]);
+
+        assert_eq!(detect_cycle(&root).map(|cycle| cycle.len()), Some(3));
+        a.clear();
+    }
+}
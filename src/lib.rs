@@ -6,12 +6,18 @@
 //! always able to tell what its siblings pointing to the same value are.
 //!
 //! This functionality is useful for manually tracking down reference cycles or other causes that
-//! prevent proper clean-up, which occasionally result in deadlocks.
+//! prevent proper clean-up, which occasionally result in deadlocks. Values able to enumerate the
+//! `Snarc`s they contain can also be searched for cycles automatically, see `detect_cycle`.
 //!
-//! `Snarc` and `Weak` are drop-in replacements for `Arc` and `Weak` respectively. However, calling
-//! the standard interfaces does not allow complete call site information to be added, so methods
-//! like `new_at_line` or `clone_at_line` should be used. In case the compatible methods like `new`,
-//! `clone`, ... are called, `Site::Unknown` is used for the resulting tracked `Origin`.
+//! `Snarc` and `Weak` are drop-in replacements for `Arc` and `Weak` respectively. The compatible
+//! methods like `new`, `clone`, ... record the location they were called from through
+//! `#[track_caller]`; methods like `new_at_line` or `clone_at_line` allow passing in the call site
+//! explicitly instead. Call sites can not be recorded when a reference is created from generic
+//! code, e.g. when a `Vec<Snarc<T>>` is cloned, in which case the location inside that code is
+//! used. Alternatively, a label for all references created on a thread can be set through
+//! `scope::push`, or the `snarc!` family of macros used to capture the site at their invocation.
+//! Frameworks can supply sites for references that would otherwise have none, e.g. those created
+//! through `From`, via `set_default_site_provider`.
 //!
 //! ```rust
 //! use snarc::Snarc;
@@ -23,7 +29,7 @@
 //! // of its origin and the line where the cloning happened.
 //! let a = foo.clone_at_line(file!(), line!());
 //!
-//! // "Regular" clone. This will not record file and line information.
+//! // "Regular" clone. The file and line of the call are recorded automatically.
 //! let b = Snarc::clone(&foo);
 //! ```
 //!
@@ -36,524 +42,952 @@
 //! ```
 //!
 //! This form allows only some instances to be annotated, or annotations being added gradually.
-
-#![feature(coerce_unsized)]
-#![feature(unsize)]
-
+//!
+//! Tracking is enabled through the `tracking` feature, which is on by default. Without it, `Snarc`
+//! and `Weak` are thin wrappers around `Arc` and `Weak` with the same API, but nothing is recorded:
+//! sites passed in are ignored, origins are placeholders and families are always empty. This allows
+//! leaving `Snarc` in place in release builds at no cost.
+//!
+//! The `registry` feature additionally keeps a process-wide record of every tracked value, allowing
+//! all outstanding references to be listed without a handle to any of them, see `registry`.
+//! The `track-access` feature counts how often each value is dereferenced, see
+//! `Snarc::access_count`; as it adds overhead to every dereference, it is off by default.
+//! With the `parking_lot` feature, the map of references is guarded by a `parking_lot::RwLock`
+//! instead of `std::sync::RwLock`. It is never poisoned, so `Snarc::try_origin` and
+//! `Snarc::try_family` always succeed, and a panic inside a `RefHook` does not render the family
+//! unusable.
+//! The `contention` feature counts how often locking a family had to wait for another thread, see
+//! `Snarc::contention_count`, to find out whether the lock is a bottleneck.
+//! For values with many references in flight across threads, the `sharded` feature adds
+//! `Snarc::new_sharded`, spreading the family across several locks.
+//! The `color` feature adds `DumpColored`, a `Dump` highlighted with ANSI colors for terminals.
+//! IDs are `usize`s, unless the `u64-ids` feature makes `Uid` a `u64`, for long-lived values with a
+//! lot of reference churn on 32-bit targets.
+//!
+//! # Unsized values
+//!
+//! Like `Arc`, a `Snarc` of a sized type can be turned into one of a slice or trait object. On
+//! stable Rust, this is done explicitly through `Snarc::unsize_with` or `Snarc::unsize_to_slice`:
+//!
+//! ```rust
+//! use std::fmt::Debug;
+//! use std::sync::Arc;
+//! use snarc::Snarc;
+//!
+//! let slice: Snarc<[u8]> = Snarc::unsize_to_slice(Snarc::new([1, 2, 3]));
+//! let debug = Snarc::unsize_with(Snarc::new(1), |arc| arc as Arc<dyn Debug>);
+//! ```
+//!
+//! With the `nightly` feature enabled, `Snarc` and `Weak` implement `CoerceUnsized`, so that these
+//! conversions happen implicitly, e.g. `let slice: Snarc<[u8]> = Snarc::new([1, 2, 3]);`. This
+//! requires a nightly compiler.
+//!
+//! # `no_std`
+//!
+//! With the `no_std` feature enabled, the crate only depends on `core` and `alloc`. The map of
+//! references is guarded by a spin lock instead of `std::sync::RwLock`, and everything requiring
+//! the operating system is left out: origins record neither their thread nor their creation and
+//! drop times, and `Snarc::site_histogram`, `Snarc::threads` and `Snarc::dump_to` are
+//! unavailable. All sites but `Site::Backtrace` remain, which, and with it the `backtrace`
+//! feature, requires `std`. `Dump` and `Dot` only need `core::fmt` and keep working.
+
+#![cfg_attr(all(feature = "no_std", not(test)), no_std)]
+#![cfg_attr(feature = "nightly", feature(coerce_unsized))]
+#![cfg_attr(feature = "nightly", feature(unsize))]
+
+#[cfg(all(feature = "no_std", feature = "backtrace"))]
+compile_error!("The `backtrace` feature requires `std` and can not be combined with `no_std`.");
+#[cfg(all(feature = "no_std", feature = "parking_lot"))]
+compile_error!("The `parking_lot` feature requires `std` and can not be combined with `no_std`.");
+
+extern crate alloc;
+#[cfg(any(not(feature = "no_std"), test))]
+extern crate core;
+#[cfg(feature = "parking_lot")]
+extern crate parking_lot;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "no_std")]
+extern crate spin;
+
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "color")]
+mod color;
+mod cycle;
+mod projection;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(not(feature = "no_std"))]
+pub mod scope;
+#[cfg(feature = "tracking")]
+mod sync;
 pub mod tracing;
+#[cfg(feature = "tracking")]
+mod tracked;
+#[cfg(not(feature = "tracking"))]
+mod untracked;
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "no_std")]
+use alloc::string::{String, ToString};
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(not(feature = "no_std"))]
+use std::thread;
+#[cfg(not(feature = "no_std"))]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "color")]
+pub use color::DumpColored;
+pub use cycle::{detect_cycle, TracksChildren};
+pub use projection::SnarcRef;
+use tracing::{FamilyTree, Link, Origin, OriginKind, Site, Uid};
+#[cfg(feature = "tracking")]
+pub use tracked::{
+    clear_default_site_provider, set_default_site_provider, Snarc, SnarcBuilder, Weak,
+};
+#[cfg(not(feature = "tracking"))]
+pub use untracked::{
+    clear_default_site_provider, set_default_site_provider, Snarc, SnarcBuilder, Weak,
+};
+
+/// Receiver of reference lifecycle events.
+///
+/// A hook is attached to a single value on creation, see `Snarc::new_with_hook`, and notified about
+/// every reference to it that is created or dropped, strong or weak. Notifications are sent while
+/// the family is locked, so the hook must not access the value's family (e.g. by cloning one of its
+/// references) or it will deadlock.
+///
+/// The hook is dropped along with the tracking state once the last strong or weak reference is
+/// gone, so weak references dropped after the value are reported as well.
+pub trait RefHook: Send + Sync {
+    /// Called after a new reference has been created.
+    fn on_create(&self, origin: &Origin);
 
-use std::collections::HashMap;
-use std::fmt;
-use std::ops::{Deref, CoerceUnsized};
-use std::sync::{Arc, Mutex, Weak as ArcWeak};
-use std::marker::Unsize;
-use std::borrow;
-
-use tracing::{Origin, OriginKind, Site, Uid};
+    /// Called after the reference with the given ID has been dropped.
+    fn on_drop(&self, id: Uid);
 
-/// Tracked reference state.
-///
-/// The `Map` tracks the number and site of references pointing toward the same value.
-#[derive(Debug)]
-struct Map {
-    strongs: HashMap<Uid, Origin>,
-    weaks: HashMap<Uid, Origin>,
-    next_id: Uid,
+    /// Called after `on_create` the first time the live references of the family span more than
+    /// one thread, with the origin of the reference created on the new thread.
+    ///
+    /// Does nothing by default. Never called with the `no_std` feature enabled, as threads are not
+    /// recorded then.
+    fn on_shared(&self, _origin: &Origin) {}
 }
 
-impl Map {
-    /// Creates a new map instance.
-    fn new() -> Map {
-        Map {
-            strongs: HashMap::with_capacity(128),
-            weaks: HashMap::with_capacity(128),
-            next_id: 0,
-        }
+impl fmt::Debug for dyn RefHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RefHook")
     }
+}
 
-    /// Increments the `next_id` counter and returns the previous value.
-    fn next_id(&mut self) -> Uid {
-        let id = self.next_id;
-        self.next_id += 1;
-        id
+// TODO: impl
+//
+// impl<T: ?Sized + PartialEq> PartialEq for Snarc<T> {
+// impl<T: ?Sized + PartialOrd> PartialOrd for Snarc<T> {
+// impl<T: ?Sized + Ord> Ord for Snarc<T> {
+// impl<T: ?Sized + Eq> Eq for Snarc<T> {}
+// impl<T: ?Sized + fmt::Display> fmt::Display for Snarc<T> {
+// impl<T: ?Sized> fmt::Pointer for Snarc<T> {
+// impl<T: Default> Default for Snarc<T> {
+// impl<T: ?Sized + Hash> Hash for Snarc<T> {
+
+/// A strong or weak reference to a value, see `same_allocation`.
+pub trait Handle<T: ?Sized> {
+    /// Returns a raw pointer to the value, see `Snarc::as_ptr` and `Weak::as_ptr`.
+    fn allocation_ptr(&self) -> *const T;
+}
+
+impl<T: ?Sized> Handle<T> for Snarc<T> {
+    fn allocation_ptr(&self) -> *const T {
+        Snarc::as_ptr(self)
     }
 }
 
-/// Inner state of `Snarc`.
-#[derive(Debug)]
-struct Inner<T: ?Sized> {
-    /// Sibling metadata.
-    map: Mutex<Map>,
-    /// The actual value.
-    data: T,
+impl<T: ?Sized> Handle<T> for Weak<T> {
+    fn allocation_ptr(&self) -> *const T {
+        self.as_ptr()
+    }
 }
 
-/// A 'snitching' atomically reference counted pointer.
+/// Returns true if the two references point to the same allocation, each either a `Snarc` or a
+/// `Weak`.
 ///
-/// A `Snarc` wraps an actual `Arc` and assigns it a unique ID upon creation. Any offspring of
-/// created via `clone` or `downgrade` is tracked by being assigned a unique ID as well. If the
-/// annotating methods `new_at_line`, `clone_at_line`, etc. are used, the `Snarc` will also know
-/// its origin.
-#[derive(Debug)]
-pub struct Snarc<T: ?Sized> {
-    /// Wrapped [std::sync] arc reference.
-    inner: Arc<Inner<T>>,
-    /// Unique ID for this instance.
-    id: Uid,
+/// Unlike comparing after upgrading, this creates no references and also works for weak
+/// references whose value is gone. As with `Weak::ptr_eq`, two `Weak`s not pointing to any
+/// allocation compare equal. Example:
+///
+/// ```rust
+/// use snarc::{same_allocation, Snarc};
+///
+/// let foo = Snarc::new(1);
+/// let weak = Snarc::downgrade(&foo);
+///
+/// assert!(same_allocation(&foo, &weak));
+/// assert!(!same_allocation(&foo, &Snarc::new(1)));
+/// ```
+pub fn same_allocation<T, A, B>(a: &A, b: &B) -> bool
+where
+    T: ?Sized,
+    A: Handle<T>,
+    B: Handle<T>,
+{
+    // Only the addresses are compared, the metadata of unsized values may differ.
+    a.allocation_ptr() as *const u8 == b.allocation_ptr() as *const u8
 }
 
-impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Snarc<U>> for Snarc<T> {}
-
-/// The non-owned version of a `Snarc`.
+/// Output helper.
+///
+/// The `Dump` struct can be used as a zero-sized wrapper to output a `Snarc`. Example:
+///
+/// ```rust
+/// use snarc::{Dump, Snarc};
+///
+/// let foo = Snarc::new(123);
+/// let bar = Snarc::clone_at_line(&foo, file!(), line!());
+/// let weak = Snarc::downgrade(&bar);
+///
+/// println!("{}", Dump(&bar));
+/// ```
+///
+/// The resulting output will be something resembling:
+///
+/// ```ignore
+/// Family associated with ID: 1
+/// S| new<0>[src/main.rs:3]{ThreadId(1)}
+/// S| clone<1>[src/main.rs:4]{ThreadId(1)} <- new<0>[src/main.rs:3]{ThreadId(1)}
+/// W| downgrade<2>[src/main.rs:5]{ThreadId(1)} <- clone<1>[src/main.rs:4]{ThreadId(1)}
+///    <- new<0>[src/main.rs:3]{ThreadId(1)}
+/// ```
+///
+/// Formatted using the alternate flag (`{:#}`), the family is rendered as a tree instead, each
+/// reference indented beneath the one it was created from. Dropped references that live ones
+/// descend from are marked `-|`:
+///
+/// ```ignore
+/// Family associated with ID: 1
+/// -| new<0>[src/main.rs:3]{ThreadId(1)}
+///   S| clone<1>[src/main.rs:4]{ThreadId(1)}
+///     W| downgrade<2>[src/main.rs:5]{ThreadId(1)}
+///   S| clone<3>[src/main.rs:6]{ThreadId(1)}
+/// ```
 #[derive(Debug)]
-pub struct Weak<T: ?Sized> {
-    /// Unique ID for this instance.
-    id: Option<Uid>,
-    /// Wrapped non-owned [std::sync] arc reference.
-    inner: ArcWeak<Inner<T>>,
-}
-
-impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Weak<U>> for Weak<T> {}
+pub struct Dump<'a, T: ?Sized + 'a>(pub &'a Snarc<T>);
 
-impl<T> Snarc<T> {
-    /// Internal instantiation function.
-    ///
-    /// Directly accepts a `Site` instance, creates the correct `Origin` with `OriginKind::New`.
-    fn new_at_site(data: T, site: Site) -> Snarc<T> {
-        let mut map = Map::new();
-        let id = map.next_id();
-
-        let origin = Origin {
-            kind: OriginKind::New,
-            site,
-            id,
-        };
-
-        map.strongs.insert(id, origin);
-
-        Snarc {
-            inner: Arc::new(Inner {
-                data,
-                map: Mutex::new(map),
-            }),
-            id,
+impl<'a, T: ?Sized + 'a> fmt::Display for Dump<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write_tree(self.0, |args| f.write_fmt(args))
+        } else {
+            write_dump(self.0, DumpFilter::All, |args| f.write_fmt(args))
         }
     }
-
-    /// Returns a new `Snarc` with the provided file name and line as the origin.
-    pub fn new_at_line(data: T, file: &'static str, line: u32) -> Snarc<T> {
-        Snarc::new_at_site(data, Site::SourceFile { file, line })
-    }
-
-    /// Creates new `Snarc` with unknown origin.
-    ///
-    /// If possible, use `new_at_line` instead.
-    pub fn new(data: T) -> Snarc<T> {
-        Snarc::new_at_site(data, Site::Unknown)
-    }
-
-    /// Returns the contained value if the `Snarc` has exactly one strong reference.
-    pub fn try_unwrap(_this: Self) -> Result<T, Self> {
-        // TODO: Make this work (currently, drop is an issue).
-
-        // let Snarc { inner, id } = this;
-
-        // match Arc::try_unwrap(inner) {
-        //     Ok(inner) => {
-        //         // We've dissolved our Snarc, as we are the last strong reference. All that's left
-        //         // are weak references, so our copy of `map` is the last one surviving and will
-        //         // be freed once we exit this function. We do not need to clean up for this reason.
-        //         Ok(inner.data)
-        //     }
-        //     Err(new_inner) => Err(Snarc {
-        //         inner: new_inner,
-        //         id,
-        //     }),
-        // }
-        unimplemented!()
-    }
 }
 
-impl<T: ?Sized> Snarc<T> {
-    /// Internal cloning function.
-    ///
-    /// Directly accepts a `Site` instance, creates the correct `Origin` with
-    /// `OriginKind::Cloned`.
-    fn clone_at_site(&self, site: Site) -> Snarc<T> {
-        let mut map = self.inner.map.lock().unwrap();
-        let parent_origin = map
-            .strongs
-            .get(&self.id)
-            .expect("Internal consistency error (clone). This should never happen.")
-            .clone();
-        let new_id = map.next_id();
-        let new_origin = Origin {
-            kind: OriginKind::Cloned(Box::new(parent_origin)),
-            site,
-            id: new_id,
-        };
-        map.strongs.insert(new_id, new_origin);
-
-        Snarc {
-            inner: self.inner.clone(),
-            id: new_id,
-        }
-    }
+/// Output helper for a `Weak`.
+///
+/// Like `Dump`, but for the family of a `Weak`, useful if no strong reference to the value is at
+/// hand. If the value is gone, a notice is printed after the header and the remaining weak
+/// references are listed as `W(dead)|`:
+///
+/// ```ignore
+/// Family associated with ID: 2
+/// Value dropped.
+/// W(dead)| downgrade<2>[src/main.rs:5]{ThreadId(1)} <- clone<1>[src/main.rs:4]{ThreadId(1)}
+///    <- new<0>[src/main.rs:3]{ThreadId(1)}
+/// ```
+///
+/// Untracked `Weak`s, e.g. those created by `Weak::new`, have no family to list.
+#[derive(Debug)]
+pub struct DumpWeak<'a, T: ?Sized + 'a>(pub &'a Weak<T>);
 
-    /// Internal downgrade function.
-    ///
-    /// Directly accepts a `Site` instance, creates the correct `Origin` with
-    /// `OriginKind::Downgraded`.
-    fn downgrade_at_site(this: &Self, site: Site) -> Weak<T> {
-        let mut map = this.inner.map.lock().unwrap();
-        // No need to `::remove` here because the strong ref will be dropped.
-        let prev_origin = map
-            .strongs
-            .get(&this.id)
-            .expect("Internal consistency error (downgrade). This should never happen.")
-            .clone();
-        let new_id = map.next_id();
-        let new_origin = Origin {
-            kind: OriginKind::Downgraded(Box::new(prev_origin)),
-            site,
-            id: new_id,
+impl<'a, T: ?Sized + 'a> fmt::Display for DumpWeak<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (id, family) = match (self.0.id(), self.0.recorded_family()) {
+            (Some(id), Some(family)) => (id, family),
+            _ => return writeln!(f, "Untracked reference."),
         };
-        map.weaks.insert(new_id, new_origin);
 
-        Weak {
-            inner: Arc::downgrade(&this.inner),
-            id: Some(new_id),
+        writeln!(f, "Family associated with ID: {}", id)?;
+        let expired = self.0.is_expired();
+        if expired {
+            writeln!(f, "Value dropped.")?;
         }
+        write_members(family, DumpFilter::All, expired, |args| f.write_fmt(args))
     }
+}
 
-    /// Clones `Snarc` with the provided file name and line as the origin.
-    pub fn clone_at_line(&self, file: &'static str, line: u32) -> Snarc<T> {
-        self.clone_at_site(Site::SourceFile { file, line })
-    }
-
-    /// Creates a new `Weak` pointer to this value with the provided file name and line as the
-    /// origin.
-    pub fn downgrade_at_line(this: &Self, file: &'static str, line: u32) -> Weak<T> {
-        Snarc::downgrade_at_site(this, Site::SourceFile { file, line })
-    }
+/// Selects the references included in a filtered dump, see `Snarc::dump_filtered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFilter<'a> {
+    /// All references, same as a regular `Dump`.
+    All,
+    /// Only strong references.
+    Strong,
+    /// Only weak references.
+    Weak,
+    /// Only references whose own site, or the site of the root of their origin chain, is a source
+    /// file with a name containing the given string.
+    File(&'a str),
+}
 
-    /// Creates a new `Weak` pointer to this value.
-    ///
-    /// If possible, use `new_at_line` instead.
-    pub fn downgrade(this: &Self) -> Weak<T> {
-        Snarc::downgrade_at_site(this, Site::Unknown)
+impl<'a> DumpFilter<'a> {
+    /// Checks whether an origin passes the filter.
+    fn matches(&self, origin: &Origin, weak: bool) -> bool {
+        match *self {
+            DumpFilter::All => true,
+            DumpFilter::Strong => !weak,
+            DumpFilter::Weak => weak,
+            DumpFilter::File(needle) => {
+                [&origin.site, &origin.root().site]
+                    .iter()
+                    .any(|site| match **site {
+                        Site::SourceFile { file, .. } => file.contains(needle),
+                        _ => false,
+                    })
+            }
+        }
     }
+}
 
-    /// Gets the number of `Weak` pointers to this value.
-    ///
-    /// See `std::sync::Arc::weak_count` for details.
-    pub fn weak_count(this: &Snarc<T>) -> usize {
-        Arc::weak_count(&this.inner)
-    }
+/// Writes the `Dump` output of a `Snarc` line by line, through `emit`.
+///
+/// Only references passing `filter` are included.
+pub(crate) fn write_dump<T, E, F>(this: &Snarc<T>, filter: DumpFilter, mut emit: F) -> Result<(), E>
+where
+    T: ?Sized,
+    F: FnMut(fmt::Arguments) -> Result<(), E>,
+{
+    emit(format_args!(
+        "Family associated with ID: {}\n",
+        Snarc::id(this)
+    ))?;
+
+    // A family with a strong reference is alive.
+    write_members(Snarc::family(this), filter, false, emit)
+}
 
-    /// Gets the number of `Snarc` pointers to this value.
-    ///
-    /// See `std::sync::Arc::strong_count` for details.
-    pub fn strong_count(this: &Snarc<T>) -> usize {
-        Arc::strong_count(&this.inner)
+/// Writes the alternate `Dump` output of a `Snarc`, the family as an indented tree, through `emit`.
+fn write_tree<T, E, F>(this: &Snarc<T>, mut emit: F) -> Result<(), E>
+where
+    T: ?Sized,
+    F: FnMut(fmt::Arguments) -> Result<(), E>,
+{
+    emit(format_args!(
+        "Family associated with ID: {}\n",
+        Snarc::id(this)
+    ))?;
+
+    let family = Snarc::family(this);
+    let weaks: Vec<Uid> = family.1.iter().map(|origin| origin.id).collect();
+    let tree = FamilyTree::new(family);
+
+    for origin in &tree {
+        let kind = if !tree.is_live(origin.id) {
+            "-"
+        } else if weaks.contains(&origin.id) {
+            "W"
+        } else {
+            "S"
+        };
+        emit(format_args!(
+            "{:indent$}{}| {}\n",
+            "",
+            kind,
+            Link(origin),
+            indent = 2 * origin.depth()
+        ))?;
     }
 
-    /// Returns true if the two Arcs point to the same value (not just values that compare as equal).
-    ///
-    /// See `std::sync::Arc::ptr_eq` for details.
-    pub fn ptr_eq(this: &Snarc<T>, other: &Snarc<T>) -> bool {
-        Arc::ptr_eq(&this.inner, &other.inner)
-    }
+    Ok(())
+}
 
-    /// Returns a mutable reference to the inner value, if there are no other Arc or Weak pointers
-    /// to the same value.
-    ///
-    /// See `std::sync::Arc::make_mut` for details.
-    pub fn get_mut(this: &mut Snarc<T>) -> Option<&mut T> {
-        Arc::get_mut(&mut this.inner).map(|inner| &mut inner.data)
+/// Writes the `S|` and `W|` lines of a `Dump` for the given family, through `emit`.
+///
+/// Only references passing `filter` are included. If the value is gone (`expired`), weak lines are
+/// marked `W(dead)|` instead.
+pub(crate) fn write_members<E, F>(
+    (strongs, weaks): (Vec<Origin>, Vec<Origin>),
+    filter: DumpFilter,
+    expired: bool,
+    mut emit: F,
+) -> Result<(), E>
+where
+    F: FnMut(fmt::Arguments) -> Result<(), E>,
+{
+    // Sort by ID, unless the family is ordered already.
+    #[cfg(not(any(feature = "no_std", feature = "ordered")))]
+    let (strongs, weaks) = {
+        let (mut strongs, mut weaks) = (strongs, weaks);
+        strongs.sort();
+        weaks.sort();
+        (strongs, weaks)
+    };
+
+    for strong in strongs
+        .iter()
+        .filter(|origin| filter.matches(origin, false))
+    {
+        emit(format_args!("S| {}\n", strong))?;
     }
-
-    /// Returns the origin chain of this reference.
-    ///
-    /// The resulting `Origin` can be printed using `fmt::Display`, see the `tracing` docs for
-    /// details.
-    pub fn origin(this: &Snarc<T>) -> Origin {
-        this.inner
-            .map
-            .lock()
-            .expect("Poisoned strong mapping. This is a bug.")
-            .strongs
-            .get(&this.id)
-            .expect("Internal consisency error (origin). This is a bug.")
-            .clone()
-    }
-
-    /// Returns the origin of the reference and all of its siblings.
-    ///
-    /// Returns a tuple of (strong origins, weak origins), including all live references.
-    pub fn family(this: &Snarc<T>) -> (Vec<Origin>, Vec<Origin>) {
-        let map = this
-            .inner
-            .map
-            .lock()
-            .expect("Poisoned strong mapping. This is a bug.");
-
-        (
-            map.strongs.values().cloned().collect(),
-            map.weaks.values().cloned().collect(),
-        )
+    for weak in weaks.iter().filter(|origin| filter.matches(origin, true)) {
+        emit(format_args!(
+            "{}| {}\n",
+            if expired { "W(dead)" } else { "W" },
+            weak
+        ))?;
     }
-}
 
-impl<T: Clone> Snarc<T> {
-    /// Makes a mutable reference into the given Arc.
-    ///
-    /// See `std::sync::Arc::make_mut` for details.
-    pub fn make_mut(_this: &mut Snarc<T>) -> &mut T {
-        unimplemented!()
-    }
+    Ok(())
 }
 
-impl<T: ?Sized> Deref for Snarc<T> {
-    type Target = T;
-
-    #[inline]
-    fn deref(&self) -> &Self::Target {
-        &self.inner.data
+/// Panics with a full `Dump` of the family, unless `this` has exactly the given reference counts.
+pub(crate) fn assert_counts<T: ?Sized>(this: &Snarc<T>, strong: usize, weak: usize, what: &str) {
+    let (actual_strong, actual_weak) = (Snarc::strong_count(this), Snarc::weak_count(this));
+
+    if (actual_strong, actual_weak) != (strong, weak) {
+        panic!(
+            "{}: expected {} strong and {} weak references, found {} and {}.\n{}",
+            what,
+            strong,
+            weak,
+            actual_strong,
+            actual_weak,
+            Dump(this)
+        );
     }
 }
 
-impl<T: ?Sized> Drop for Snarc<T> {
-    fn drop(&mut self) {
-        let mut map = self.inner.map.lock().unwrap();
-        map.strongs
-            .remove(&self.id)
-            .expect("Internal consistency error (drop)");
-    }
-}
+/// Longest pause between two checks of `wait_for`.
+#[cfg(not(feature = "no_std"))]
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
 
-impl<T: ?Sized> Clone for Snarc<T> {
-    fn clone(&self) -> Self {
-        self.clone_at_site(Site::Unknown)
+/// Waits until `done` returns true, checking with increasing pauses in between, or until `timeout`
+/// has passed.
+///
+/// A timeout too long to represent as an `Instant` waits indefinitely.
+#[cfg(not(feature = "no_std"))]
+pub(crate) fn wait_for<F: FnMut() -> bool>(timeout: Duration, mut done: F) {
+    let deadline = Instant::now().checked_add(timeout);
+    let mut backoff = Duration::from_micros(1);
+
+    while !done() {
+        let pause = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if left > Duration::ZERO => backoff.min(left),
+                _ => return,
+            },
+            None => backoff,
+        };
+        thread::sleep(pause);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
     }
 }
 
-impl<T: ?Sized> borrow::Borrow<T> for Snarc<T> {
-    fn borrow(&self) -> &T {
-        &**self
-    }
+/// Leak check guard.
+///
+/// Records the number of strong and weak references to a value upon creation and panics with a
+/// `Dump` of the family if these numbers differ once the guard is dropped, e.g. because a reference
+/// created in the guarded scope is still around. Example:
+///
+/// ```rust,should_panic
+/// use snarc::{LeakGuard, Snarc};
+///
+/// let foo = Snarc::new(123);
+/// let mut leaked = Vec::new();
+///
+/// {
+///     let _guard = LeakGuard::new(&foo);
+///     leaked.push(foo.clone());
+/// } // Panics, `leaked` still holds a reference.
+/// ```
+///
+/// If the thread is already panicking, the check is skipped to avoid aborting. This requires `std`;
+/// with the `no_std` feature, a failed check while panicking aborts instead.
+#[derive(Debug)]
+#[must_use = "the leak check happens when the guard is dropped"]
+pub struct LeakGuard<'a, T: ?Sized + 'a> {
+    /// The guarded reference.
+    this: &'a Snarc<T>,
+    /// Number of strong references at creation.
+    strong: usize,
+    /// Number of weak references at creation.
+    weak: usize,
 }
 
-impl<T: ?Sized> AsRef<T> for Snarc<T> {
-    fn as_ref(&self) -> &T {
-        &**self
+impl<'a, T: ?Sized + 'a> LeakGuard<'a, T> {
+    /// Creates a new guard, using the current reference counts of `this` as the baseline.
+    pub fn new(this: &'a Snarc<T>) -> LeakGuard<'a, T> {
+        LeakGuard {
+            this,
+            strong: Snarc::strong_count(this),
+            weak: Snarc::weak_count(this),
+        }
     }
 }
 
-
-impl<T: ?Sized> Weak<T> {
-    /// Internal upgrade function.
-    ///
-    /// Directly accepts a `Site` instance, creates the correct `Origin` with
-    /// `OriginKind::Upgraded`.
-    pub fn upgrade_at_site(&self, site: Site) -> Option<Snarc<T>> {
-        let id = self.id?;
-
-        self.inner.upgrade().map(|inner| {
-            let id = {
-                let mut map = inner.map.lock().unwrap();
-                let prev_origin = map
-                    .weaks
-                    .get(&id)
-                    .expect("Internal consistency error (upgrade)")
-                    .clone();
-                let new_id = map.next_id();
-                let new_origin = Origin {
-                    kind: OriginKind::Upgraded(Box::new(prev_origin)),
-                    site,
-                    id: new_id,
-                };
-                map.strongs.insert(new_id, new_origin);
-                new_id
-            };
-            Snarc { inner, id }
-        })
-    }
-
-    /// Internal cloning function.
-    ///
-    /// Directly accepts a `Site` instance, creates the correct `Origin` with
-    /// `OriginKind::Cloned`.
-    fn clone_at_site(&self, site: Site) -> Weak<T> {
-        // We need to create a temporary untracked strong reference here, no way around it.
-        //
-        // The issue is that we need access to the data, which might be gone already, real `Weak`s
-        // never have this issue.
-
-        match self.inner.upgrade() {
-            Some(strong) => {
-                // The accompanying strong reference still exists, so we can perform a "proper"
-                // clone.
-                let mut map = strong.map.lock().unwrap();
-
-                let our_id = self.id.expect(
-                    "Succesfully upgraded a weak reference, but it has no ID.\
-                     This should never happen.",
-                );
-
-                let parent_origin = map
-                    .weaks
-                    .get(&our_id)
-                    .expect("Internal consistency error (weak clone). This should never happen.")
-                    .clone();
-                let new_id = map.next_id();
-                let new_origin = Origin {
-                    kind: OriginKind::Cloned(Box::new(parent_origin)),
-                    site,
-                    id: new_id,
-                };
-                map.weaks.insert(new_id, new_origin);
-
-                Weak {
-                    inner: self.inner.clone(),
-                    id: Some(new_id),
-                }
-            }
-            None => {
-                // We cloned a dead weak ref. We already lost all of our tracking info, so there
-                // is nothing we can do. Just hand out a weak ref, with no ID.
-                Weak {
-                    inner: self.inner.clone(),
-                    id: None,
-                }
+impl<'a, T: ?Sized + 'a> Drop for LeakGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "no_std"))]
+        {
+            if ::std::thread::panicking() {
+                return;
             }
         }
-    }
 
-    /// Attempts to upgrade the Weak pointer to an Arc, extending the lifetime of the value if
-    /// successful.
-    ///
-    /// See `std::sync::Weak::upgrade` for details.
-    pub fn upgrade_at_line(&self, file: &'static str, line: u32) -> Option<Snarc<T>> {
-        self.upgrade_at_site(Site::SourceFile { file, line })
+        assert_counts(self.this, self.strong, self.weak, "References leaked");
     }
+}
 
-    /// Attempts to upgrade the Weak pointer to an Arc, extending the lifetime of the value if
-    /// successful.
-    ///
-    /// If possible, use `upgrade_at_line` instead.
-    pub fn upgrade(&self) -> Option<Snarc<T>> {
-        self.upgrade_at_site(Site::Unknown)
-    }
+/// Error returned by `Snarc::try_get_mut` if the value is shared.
+///
+/// Carries the reference counts at the time of the failed borrow, along with a `Dump` of the
+/// family, so the references blocking mutation can be told apart. Displaying the error prints both:
+///
+/// ```ignore
+/// Value is shared by 2 strong and 1 weak references.
+/// Family associated with ID: 0
+/// S| new<0>[src/main.rs:3]{ThreadId(1)}
+/// S| clone<1>[src/main.rs:4]{ThreadId(1)} <- new<0>[src/main.rs:3]{ThreadId(1)}
+/// W| downgrade<2>[src/main.rs:5]{ThreadId(1)} <- new<0>[src/main.rs:3]{ThreadId(1)}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedError {
+    /// Number of strong references, including the one the borrow was attempted through.
+    pub strong: usize,
+    /// Number of weak references.
+    pub weak: usize,
+    /// The `Dump` of the family, as rendered when the borrow failed.
+    pub dump: String,
 }
 
-impl<T: ?Sized> Drop for Weak<T> {
-    fn drop(&mut self) {
-        if let Some(inner) = self.inner.upgrade() {
-            let mut map = inner.map.lock().unwrap();
-            let our_id = self
-                .id
-                .expect("No ID on alive weak reference in drop. This is a bug.");
-
-            map.weaks
-                .remove(&our_id)
-                .expect("Internal consistency error (drop). This is a bug.");
+impl SharedError {
+    /// Captures the current state of the family of `this`.
+    pub(crate) fn new<T: ?Sized>(this: &Snarc<T>) -> SharedError {
+        SharedError {
+            strong: Snarc::strong_count(this),
+            weak: Snarc::weak_count(this),
+            dump: Dump(this).to_string(),
         }
     }
 }
 
-impl<T: ?Sized> Clone for Weak<T> {
-    fn clone(&self) -> Self {
-        self.clone_at_site(Site::Unknown)
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Value is shared by {} strong and {} weak references.",
+            self.strong, self.weak
+        )?;
+        f.write_str(&self.dump)
     }
 }
 
-// TODO: impl
-//
-// impl<T> Default for Weak<T> {
-//
-// impl<T: ?Sized + PartialEq> PartialEq for Snarc<T> {
-// impl<T: ?Sized + PartialOrd> PartialOrd for Snarc<T> {
-// impl<T: ?Sized + Ord> Ord for Snarc<T> {
-// impl<T: ?Sized + Eq> Eq for Snarc<T> {}
-// impl<T: ?Sized + fmt::Display> fmt::Display for Snarc<T> {
-// impl<T: ?Sized + fmt::Debug> fmt::Debug for Snarc<T> { // Manual impl?
-// impl<T: ?Sized> fmt::Pointer for Snarc<T> {
-// impl<T: Default> Default for Snarc<T> {
-// impl<T: ?Sized + Hash> Hash for Snarc<T> {
-// impl<T> From<T> for Snarc<T> {
-// impl<'a, T: Clone> From<&'a [T]> for Snarc<[T]> {
-// impl<'a> From<&'a str> for Snarc<str> {
-// impl From<String> for Snarc<str> {
-// impl<T: ?Sized> From<Box<T>> for Snarc<T> {
-// impl<T> From<Vec<T>> for Snarc<[T]> {
-
+#[cfg(not(feature = "no_std"))]
+impl ::std::error::Error for SharedError {}
 
-/// Output helper.
+/// Graphviz output helper.
 ///
-/// The `Dump` struct can be used as a zero-sized wrapper to output a `Snarc`. Example:
+/// Like `Dump`, the `Dot` struct wraps a `Snarc` for output, rendering its family as a
+/// [DOT](https://graphviz.org/doc/info/lang.html) digraph instead. Every reference is a node,
+/// labeled with its kind, ID and site, and an edge leads from each parent to the reference created
+/// from it. Weak references are drawn dashed; references that are already gone but still part of
+/// an origin chain are grayed out. Example:
 ///
 /// ```rust
-/// use snarc::{Dump, Snarc};
+/// use snarc::{Dot, Snarc};
 ///
 /// let foo = Snarc::new(123);
 /// let bar = Snarc::clone_at_line(&foo, file!(), line!());
 /// let weak = Snarc::downgrade(&bar);
 ///
-/// println!("{}", Dump(&bar));
-/// ```
-///
-/// The resulting output will be something resembling:
-///
-/// ```ignore
-/// Family associated with ID: 1
-/// S| new<0>[?]
-/// S| clone<1>[src/lib.rs:475] <- new<0>[?]
-/// W| downgrade<2>[?] <- clone<1>[src/lib.rs:475] <- new<0>[?]
+/// println!("{}", Dot(&bar));
 /// ```
 #[derive(Debug)]
-pub struct Dump<'a, T: 'a>(pub &'a Snarc<T>);
+pub struct Dot<'a, T: ?Sized + 'a>(pub &'a Snarc<T>);
 
-impl<'a, T: 'a> fmt::Display for Dump<'a, T> {
+impl<'a, T: ?Sized + 'a> fmt::Display for Dot<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Family associated with ID: {}", self.0.id)?;
+        let (strongs, weaks) = Snarc::family(self.0);
 
-        let (mut strongs, mut weaks) = Snarc::family(self.0);
+        // Live references first, so that they are not mistaken for dead ancestors.
+        let mut nodes: BTreeMap<Uid, (&Origin, bool)> = BTreeMap::new();
+        for origin in strongs.iter().chain(weaks.iter()) {
+            nodes.insert(origin.id, (origin, true));
+        }
+        for origin in strongs.iter().chain(weaks.iter()) {
+            for ancestor in origin.ancestors().skip(1) {
+                nodes.entry(ancestor.id).or_insert((ancestor, false));
+            }
+        }
 
-        // Sort by ID.
-        strongs.sort();
-        weaks.sort();
+        writeln!(f, "digraph family {{")?;
+
+        for (id, &(origin, live)) in &nodes {
+            write!(
+                f,
+                "    {} [label=\"{}<{}>\\n{}\"",
+                id,
+                origin.kind.name(),
+                id,
+                escape_dot(&origin.site.to_string())
+            )?;
+            if is_weak(origin) {
+                write!(f, ", style=dashed")?;
+            }
+            if !live {
+                write!(f, ", color=gray, fontcolor=gray")?;
+            }
+            writeln!(f, "];")?;
+        }
 
-        for strong in strongs {
-            writeln!(f, "S| {}", strong)?;
+        for (id, &(origin, _)) in &nodes {
+            if let Some(parent) = origin.kind.parent() {
+                write!(f, "    {} -> {}", parent.id, id)?;
+                if is_weak(origin) {
+                    write!(f, " [style=dashed]")?;
+                }
+                writeln!(f, ";")?;
+            }
         }
-        for weak in weaks {
-            writeln!(f, "W| {}", weak)?;
+
+        writeln!(f, "}}")
+    }
+}
+
+/// Determines whether a reference with the given origin is a weak one.
+fn is_weak(origin: &Origin) -> bool {
+    for link in origin.ancestors() {
+        match link.kind {
+            OriginKind::New | OriginKind::Upgraded(_) => return false,
+            OriginKind::Downgraded(_) => return true,
+            OriginKind::Cloned(_) => (),
         }
+    }
 
-        Ok(())
+    unreachable!("Origin chain without a root. This should never happen.")
+}
+
+/// Escapes a string for use inside a quoted DOT identifier.
+fn escape_dot(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
     }
+
+    escaped
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Snarc;
+    use super::{Dump, LeakGuard, Snarc, Weak};
+    use std::borrow::Borrow;
+    use std::marker::PhantomPinned;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::ptr;
+    #[cfg(not(feature = "no_std"))]
+    use std::thread;
+    #[cfg(not(feature = "no_std"))]
+    use std::time::Duration;
+
+    /// Runs `f`, expecting it to panic, and returns the panic message.
+    fn panic_message<F: FnOnce()>(f: F) -> String {
+        *catch_unwind(AssertUnwindSafe(f))
+            .unwrap_err()
+            .downcast::<String>()
+            .expect("Panicked with a non-`String` payload")
+    }
+
+    #[test]
+    fn api_in_both_modes() {
+        let mut a = Snarc::new_at_line(1, file!(), line!());
+        let b = a.clone_at_line(file!(), line!());
+        let w = Snarc::downgrade_at_line(&b, file!(), line!());
+        let c = w.upgrade_at_line(file!(), line!()).unwrap();
+
+        assert_eq!(*c, 1);
+        assert!(Snarc::ptr_eq(&a, &c));
+        assert_eq!(Snarc::strong_count(&a), 3);
+        assert_eq!(Snarc::weak_count(&a), 1);
+
+        drop((b, c));
+        drop(w);
+        *Snarc::get_mut(&mut a).unwrap() += 1;
+        assert_eq!(Snarc::into_inner(a), Some(2));
+        assert!(Weak::<u8>::new().upgrade().is_none());
+    }
+
+    #[test]
+    fn same_allocation() {
+        let a = Snarc::new(1);
+        let b = a.clone();
+        let w = Snarc::downgrade(&a);
+        let other = Snarc::new(1);
+        let v = Snarc::downgrade(&other);
+
+        assert!(super::same_allocation(&a, &b));
+        assert!(super::same_allocation(&a, &w));
+        assert!(super::same_allocation(&w, &b));
+        assert!(super::same_allocation(&w, &w.clone()));
+        assert!(!super::same_allocation(&a, &other));
+        assert!(!super::same_allocation(&a, &v));
+        assert!(!super::same_allocation(&w, &v));
+
+        // Expired weaks keep pointing to their allocation.
+        drop(other);
+        assert!(v.upgrade().is_none());
+        assert!(!super::same_allocation(&a, &v));
+        assert!(super::same_allocation(&v, &v.clone()));
+
+        assert!(super::same_allocation(&Weak::<i32>::new(), &Weak::new()));
+        assert!(!super::same_allocation(&a, &Weak::new()));
+    }
 
     #[test]
-    fn basic() {
-        let thing = ();
-        let thing_strong_0 = Snarc::new_at_line(thing, file!(), line!());
-        let thing_strong_1 = thing_strong_0.clone_at_line(file!(), line!());
-        let thing_weak_0 = Snarc::downgrade_at_line(&thing_strong_0, file!(), line!());
-        let thing_weak_1 = Snarc::downgrade_at_line(&thing_strong_0, file!(), line!());
-        let thing_strong_2 = thing_weak_0.upgrade_at_line(file!(), line!());
-
-        println!("\nthing_strong_0: {:?}", thing_strong_0);
-        println!("\nthing_strong_1: {:?}", thing_strong_1);
-        println!("\nthing_weak_0: {:?}", thing_weak_0);
-        println!("\nthing_weak_1: {:?}", thing_weak_1);
-        println!("\nthing_strong_2: {:?}", thing_strong_2);
-
-        // TODO: Actually check something.
+    fn str_borrow() {
+        let key: Snarc<str> = Snarc::from("foo");
+
+        let borrowed: &str = key.borrow();
+        assert_eq!(borrowed, "foo");
+        let bytes: &[u8] = key.as_ref();
+        assert_eq!(bytes, b"foo");
+    }
+
+    #[test]
+    fn as_ptr() {
+        let a = Snarc::new(1);
+        let b = a.clone();
+        let other = Snarc::new(1);
+
+        assert_eq!(Snarc::as_ptr(&a), Snarc::as_ptr(&b));
+        assert_eq!(Snarc::as_ptr(&a), &*a as *const i32);
+        assert_ne!(Snarc::as_ptr(&a), Snarc::as_ptr(&other));
+
+        let w = Snarc::downgrade(&a);
+        drop(a);
+        assert_eq!(Snarc::as_ptr(&w.upgrade().unwrap()), Snarc::as_ptr(&b));
+    }
+
+    #[test]
+    fn pin() {
+        /// Compiles only for `Unpin` types.
+        fn assert_unpin<T: Unpin>(_: &T) {}
+
+        let p = Snarc::pin((1, PhantomPinned));
+        assert_eq!(p.0, 1);
+        assert_unpin(&p);
+
+        let q = p.clone();
+        assert!(ptr::eq(&*p, &*q));
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn try_unwrap_timeout() {
+        let a = Snarc::new(1);
+        let b = a.clone();
+        let a = Snarc::try_unwrap_timeout(a, Duration::from_millis(10)).unwrap_err();
+
+        let dropper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(b);
+        });
+        assert_eq!(
+            Snarc::try_unwrap_timeout(a, Duration::from_secs(10)).ok(),
+            Some(1)
+        );
+        dropper.join().unwrap();
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn try_unwrap_timeout_expired() {
+        let a = Snarc::new(1);
+        let b = a.clone();
+
+        // `b` is held by another thread for longer than the timeout.
+        let (release, released) = ::std::sync::mpsc::channel::<()>();
+        let holder = thread::spawn(move || {
+            released.recv().unwrap();
+            drop(b);
+        });
+        let a = Snarc::try_unwrap_timeout(a, Duration::from_millis(20)).unwrap_err();
+        assert_eq!((*a, Snarc::strong_count(&a)), (1, 2));
+
+        release.send(()).unwrap();
+        holder.join().unwrap();
+        assert_eq!(Snarc::try_unwrap_timeout(a, Duration::ZERO).ok(), Some(1));
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn try_unwrap_timeout_max() {
+        // Does not overflow computing the deadline, and returns right away if already unique.
+        let a = Snarc::new(1);
+        assert_eq!(Snarc::try_unwrap_timeout(a, Duration::MAX).ok(), Some(1));
+
+        let a = Snarc::new(2);
+        let b = a.clone();
+        let dropper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(b);
+        });
+        assert_eq!(Snarc::try_unwrap_timeout(a, Duration::MAX).ok(), Some(2));
+        dropper.join().unwrap();
+    }
+
+    #[test]
+    fn is_unique() {
+        let mut a = Snarc::new(1);
+        assert!(Snarc::is_unique(&a));
+        assert!(Snarc::get_mut(&mut a).is_some());
+
+        let b = a.clone();
+        assert!(!Snarc::is_unique(&a));
+        assert!(Snarc::get_mut(&mut a).is_none());
+        drop(b);
+
+        let w = Snarc::downgrade(&a);
+        assert!(!Snarc::is_unique(&a));
+        assert!(Snarc::get_mut(&mut a).is_none());
+        drop(w);
+
+        assert!(Snarc::is_unique(&a));
+    }
+
+    #[test]
+    fn borrow_mut() {
+        let mut a = Snarc::new(1);
+        *Snarc::borrow_mut(&mut a).unwrap() += 1;
+
+        let b = a.clone();
+        assert!(Snarc::borrow_mut(&mut a).is_none());
+        drop(b);
+
+        assert_eq!(Snarc::borrow_mut(&mut a), Some(&mut 2));
+    }
+
+    #[test]
+    fn assert_unique() {
+        let a = Snarc::new_at_line(1, "foo.rs", 1);
+        Snarc::assert_unique(&a);
+
+        let b = a.clone_at_line("foo.rs", 2);
+        let msg = panic_message(|| Snarc::assert_unique(&a));
+        assert!(msg.starts_with(
+            "Snarc is not unique: expected 1 strong and 0 weak references, found 2 and 0.\n"
+        ));
+        #[cfg(feature = "tracking")]
+        assert!(msg.contains("S| clone<1>[foo.rs:2]"));
+
+        drop(b);
+        let w = Snarc::downgrade(&a);
+        let msg = panic_message(|| Snarc::assert_unique(&a));
+        assert!(msg.contains("found 1 and 1."));
+        drop(w);
+
+        Snarc::assert_unique(&a);
+    }
+
+    #[test]
+    fn try_get_mut() {
+        let mut a = Snarc::new_at_line(1, "foo.rs", 1);
+        *Snarc::try_get_mut(&mut a).unwrap() += 1;
+        assert_eq!(*a, 2);
+
+        let b = a.clone_at_line("foo.rs", 2);
+        let w = Snarc::downgrade_at_line(&b, "foo.rs", 3);
+        let err = Snarc::try_get_mut(&mut a).unwrap_err();
+        assert_eq!((err.strong, err.weak), (2, 1));
+        assert_eq!(err.dump, Dump(&a).to_string());
+
+        let msg = err.to_string();
+        assert!(msg.starts_with("Value is shared by 2 strong and 1 weak references.\n"));
+        #[cfg(feature = "tracking")]
+        {
+            assert!(msg.contains("S| clone<1>[foo.rs:2]"));
+            assert!(msg.contains("W| downgrade<2>[foo.rs:3]"));
+        }
+
+        drop((b, w));
+        assert_eq!(*Snarc::try_get_mut(&mut a).unwrap(), 2);
+    }
+
+    #[test]
+    fn leak_guard() {
+        let a = Snarc::new_at_line(1, "foo.rs", 1);
+        let w = Snarc::downgrade(&a);
+
+        {
+            let _guard = LeakGuard::new(&a);
+            let b = a.clone();
+            let v = Snarc::downgrade(&b);
+            drop((b, v));
+        }
+
+        let mut leaked = Vec::new();
+        let msg = panic_message(|| {
+            let _guard = LeakGuard::new(&a);
+            leaked.push(a.clone_at_line("foo.rs", 3));
+        });
+        assert!(msg.starts_with(
+            "References leaked: expected 1 strong and 1 weak references, found 2 and 1.\n"
+        ));
+        #[cfg(feature = "tracking")]
+        assert!(msg.contains("S| clone<4>[foo.rs:3]"));
+
+        drop((leaked, w));
+    }
+
+    #[test]
+    #[cfg(feature = "tracking")]
+    fn tracking_enabled() {
+        use super::DumpFilter;
+
+        let a = Snarc::new_at_line(1, "foo.rs", 1);
+        let b = a.clone_at_line("foo.rs", 2);
+        let w = Snarc::downgrade(&b);
+
+        assert_eq!(Snarc::id(&b), 1);
+        assert_eq!(w.id(), Some(2));
+        assert_eq!(Snarc::origin(&b).to_string().matches("foo.rs").count(), 2);
+        assert_eq!(Snarc::family(&a).0.len(), 2);
+        assert_eq!(Snarc::family(&a).1.len(), 1);
+        assert_eq!(Dump(&a).to_string().lines().count(), 4);
+        assert_eq!(
+            Snarc::dump_filtered(&a, DumpFilter::Weak).lines().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn write_members_marks_expired_weaks() {
+        use super::{write_members, DumpFilter};
+        use core::fmt::Write;
+        use tracing::{Origin, OriginKind, Site};
+
+        let members = |expired| {
+            let weak = Origin::new(1, Site::Annotated("w".to_string()), OriginKind::New);
+            let mut output = String::new();
+            write_members((Vec::new(), vec![weak]), DumpFilter::All, expired, |args| {
+                output.write_fmt(args)
+            })
+            .unwrap();
+            output
+        };
+
+        assert!(members(false).starts_with("W| new<1>[\"w\"]"));
+        assert!(members(true).starts_with("W(dead)| new<1>[\"w\"]"));
     }
 }
@@ -0,0 +1,101 @@
+//! Macros capturing the call site explicitly.
+//!
+//! Each macro expands to the corresponding `_at_line` method, passing in the file and line the
+//! macro is invoked at, which makes explicitly annotated references as terse as plain ones:
+//!
+//! ```rust
+//! #[macro_use]
+//! extern crate snarc;
+//!
+//! # fn main() {
+//! let foo = snarc!(vec![1, 2, 3]);
+//! let bar = snarc_clone!(foo);
+//! let weak = snarc_downgrade!(bar);
+//! let baz = snarc_upgrade!(weak).unwrap();
+//! # }
+//! ```
+
+/// Creates a new `Snarc` with the location of the macro invocation as the origin.
+///
+/// Expands to `Snarc::new_at_line(value, file!(), line!())`.
+#[macro_export]
+macro_rules! snarc {
+    ($value:expr) => {
+        $crate::Snarc::new_at_line($value, file!(), line!())
+    };
+}
+
+/// Creates a new `Snarc` with the path of the module the macro is invoked in as the origin.
+///
+/// Expands to `Snarc::new_in_module(value, module_path!())`.
+#[macro_export]
+macro_rules! snarc_in_module {
+    ($value:expr) => {
+        $crate::Snarc::new_in_module($value, module_path!())
+    };
+}
+
+/// Clones a `Snarc` with the location of the macro invocation as the origin.
+///
+/// Expands to `Snarc::clone_at_line(&snarc, file!(), line!())`.
+#[macro_export]
+macro_rules! snarc_clone {
+    ($snarc:expr) => {
+        $crate::Snarc::clone_at_line(&$snarc, file!(), line!())
+    };
+}
+
+/// Downgrades a `Snarc` with the location of the macro invocation as the origin.
+///
+/// Expands to `Snarc::downgrade_at_line(&snarc, file!(), line!())`.
+#[macro_export]
+macro_rules! snarc_downgrade {
+    ($snarc:expr) => {
+        $crate::Snarc::downgrade_at_line(&$snarc, file!(), line!())
+    };
+}
+
+/// Upgrades a `Weak` with the location of the macro invocation as the origin.
+///
+/// Expands to `Weak::upgrade_at_line(&weak, file!(), line!())`, returning an `Option`.
+#[macro_export]
+macro_rules! snarc_upgrade {
+    ($weak:expr) => {
+        $crate::Weak::upgrade_at_line(&$weak, file!(), line!())
+    };
+}
+
+#[cfg(all(test, feature = "tracking"))]
+mod tests {
+    use tracing::Site;
+    use Snarc;
+
+    /// Returns the site at the given line of this file.
+    fn here(line: u32) -> Site {
+        Site::SourceFile {
+            file: file!(),
+            line,
+            column: None,
+        }
+    }
+
+    #[test]
+    fn sites() {
+        let (s, s_line) = (snarc!(1), line!());
+        let (t, t_line) = (snarc_clone!(s), line!());
+        let (w, w_line) = (snarc_downgrade!(t), line!());
+        let (u, u_line) = (snarc_upgrade!(w).unwrap(), line!());
+
+        assert_eq!(Snarc::origin(&s).site, here(s_line));
+        assert_eq!(Snarc::origin(&t).site, here(t_line));
+        assert_eq!(Snarc::origin(&u).site, here(u_line));
+        assert_eq!(
+            Snarc::origin(&u).kind.parent().map(|w| w.site.clone()),
+            Some(here(w_line))
+        );
+        assert_eq!(*u, 1);
+
+        let m = snarc_in_module!(2);
+        assert_eq!(Snarc::origin(&m).site, Site::Module(module_path!()));
+    }
+}
@@ -0,0 +1,124 @@
+//! References projected into a part of a shared value.
+
+use core::fmt;
+use core::ops::Deref;
+
+use Snarc;
+
+/// A reference to a part of a value, e.g. a field, keeping the whole value alive.
+///
+/// Created by `Snarc::map`. Holds on to the `Snarc` it was created from, so the value remains
+/// tracked as part of its family, while dereferencing to the projected part `U` of the value `T`.
+/// Cloning a `SnarcRef` clones the underlying `Snarc`, recording the location of the caller.
+pub struct SnarcRef<T: ?Sized, U: ?Sized> {
+    /// Reference keeping the value alive.
+    owner: Snarc<T>,
+    /// Projected part of the value, valid for as long as `owner` is.
+    part: *const U,
+}
+
+// Behaves like a `Snarc<T>` bundled with a `&U`.
+unsafe impl<T: ?Sized, U: ?Sized + Sync> Send for SnarcRef<T, U> where Snarc<T>: Send {}
+unsafe impl<T: ?Sized, U: ?Sized + Sync> Sync for SnarcRef<T, U> where Snarc<T>: Sync {}
+
+impl<T: ?Sized, U: ?Sized> SnarcRef<T, U> {
+    /// Projects the value `owner` points to using `f`, see `Snarc::map`.
+    pub(crate) fn new<F: FnOnce(&T) -> &U>(owner: Snarc<T>, f: F) -> SnarcRef<T, U> {
+        let part: *const U = f(&owner);
+        SnarcRef { owner, part }
+    }
+
+    /// Projects further into the referenced part, keeping the same `Snarc`.
+    pub fn map<V: ?Sized, F: FnOnce(&U) -> &V>(this: SnarcRef<T, U>, f: F) -> SnarcRef<T, V> {
+        let part: *const V = f(&this);
+        SnarcRef {
+            owner: this.owner,
+            part,
+        }
+    }
+
+    /// Returns the `Snarc` keeping the value alive.
+    pub fn owner(this: &SnarcRef<T, U>) -> &Snarc<T> {
+        &this.owner
+    }
+
+    /// Discards the projection, returning the `Snarc` keeping the value alive.
+    pub fn into_owner(this: SnarcRef<T, U>) -> Snarc<T> {
+        this.owner
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Deref for SnarcRef<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // Safe, as the value, including the projected part, is neither moved nor dropped while
+        // `owner` is alive, and only ever shared.
+        unsafe { &*self.part }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Clone for SnarcRef<T, U> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        SnarcRef {
+            owner: self.owner.clone(),
+            part: self.part,
+        }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug for SnarcRef<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnarcRef")
+            .field("id", &Snarc::id(&self.owner))
+            .field("value", &&**self)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SnarcRef;
+    use Snarc;
+
+    struct Connection {
+        name: String,
+        retries: (u32, u32),
+    }
+
+    #[test]
+    fn projects_into_field() {
+        let conn = Snarc::new(Connection {
+            name: "db".to_owned(),
+            retries: (1, 2),
+        });
+        let weak = Snarc::downgrade(&conn);
+
+        let name = Snarc::map(conn.clone(), |conn| conn.name.as_str());
+        let retries = Snarc::map(conn.clone(), |conn| &conn.retries);
+        let first = SnarcRef::map(retries.clone(), |retries| &retries.0);
+
+        assert_eq!(&*name, "db");
+        assert_eq!(*retries, (1, 2));
+        assert_eq!(*first, 1);
+        assert_eq!(Snarc::strong_count(&conn), 4);
+
+        // The projections keep the value alive as tracked members of the family.
+        #[cfg(feature = "tracking")]
+        let id = Snarc::id(SnarcRef::owner(&name));
+        drop(conn);
+        assert_eq!(&*name, "db");
+        #[cfg(feature = "tracking")]
+        assert!(Snarc::family(SnarcRef::owner(&name))
+            .0
+            .iter()
+            .any(|origin| origin.id == id));
+
+        drop((name, retries));
+        let owner = SnarcRef::into_owner(first);
+        assert_eq!(owner.retries.1, 2);
+        drop(owner);
+        assert!(weak.upgrade().is_none());
+    }
+}
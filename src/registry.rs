@@ -0,0 +1,118 @@
+//! Process-wide registry of live tracked values.
+//!
+//! With the `registry` feature enabled, every family of references is recorded in a global
+//! registry upon creation of its value, allowing all outstanding references to be inspected
+//! without a handle to any of them, e.g. to list leaked values at shutdown:
+//!
+//! ```rust
+//! use snarc::{registry, Snarc};
+//!
+//! let foo = Snarc::new(123);
+//! assert!(registry::live_allocations() >= 1);
+//!
+//! println!("{}", registry::dump_all());
+//! ```
+//!
+//! The registry only holds weak pointers to the tracking state of each family and never to the
+//! values themselves, so it does not keep anything alive. A family is counted until its last
+//! tracked `Snarc` is dropped, even if plain `Arc`s to the value remain, e.g. after
+//! `Snarc::into_arc`.
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "no_std")]
+use alloc::string::String;
+use alloc::sync::{Arc, Weak as ArcWeak};
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+use core::fmt;
+
+use sync::RwLock;
+use tracked::Tracker;
+use {write_members, DumpFilter};
+
+/// All tracking states created and not dropped yet, by address.
+static REGISTRY: RwLock<BTreeMap<usize, ArcWeak<Tracker>>> = RwLock::new(BTreeMap::new());
+
+/// Adds the tracking state of a new family to the registry.
+pub(crate) fn register(tracker: &Arc<Tracker>) {
+    REGISTRY
+        .write()
+        .insert(Arc::as_ptr(tracker) as usize, Arc::downgrade(tracker));
+}
+
+/// Removes the tracking state at the given address from the registry, as it is being dropped.
+pub(crate) fn unregister(tracker: *const Tracker) {
+    REGISTRY.write_recovering().remove(&(tracker as usize));
+}
+
+/// Returns the tracking states of all families whose value is still alive.
+fn live() -> Vec<Arc<Tracker>> {
+    let trackers: Vec<_> = REGISTRY
+        .read()
+        .values()
+        .filter_map(ArcWeak::upgrade)
+        .collect();
+
+    // The registry is no longer locked here, which matters should one of the families have died in
+    // the meantime: its tracker unregisters itself when the last pointer to it is dropped below.
+    trackers
+        .into_iter()
+        .filter(|tracker| tracker.is_alive())
+        .collect()
+}
+
+/// Returns the number of tracked values that are currently alive.
+pub fn live_allocations() -> usize {
+    live().len()
+}
+
+/// Returns the same listing as `Dump` for every tracked value that is currently alive.
+///
+/// Each family is introduced by a header line containing the address of its tracking state.
+pub fn dump_all() -> String {
+    let mut output = String::new();
+
+    for tracker in live() {
+        fmt::Write::write_fmt(
+            &mut output,
+            format_args!("Family at {:p}:\n", Arc::as_ptr(&tracker)),
+        )
+        .and_then(|_| {
+            write_members(tracker.family(), DumpFilter::All, false, |args| {
+                fmt::Write::write_fmt(&mut output, args)
+            })
+        })
+        .expect("Writing to a String failed. This should never happen.");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump_all, live_allocations};
+    use Snarc;
+
+    #[test]
+    fn counts_independent_snarcs() {
+        let a = Snarc::new_annotated(1, "registry test a");
+        let b = Snarc::new_annotated("two", "registry test b");
+        let c = Snarc::new_annotated(vec![3], "registry test c");
+        let b2 = b.clone_annotated("registry test b2");
+        let w = Snarc::downgrade(&c);
+
+        // Other tests run concurrently, so only a lower bound can be checked.
+        assert!(live_allocations() >= 3);
+
+        let dump = dump_all();
+        for label in &["a", "b", "b2", "c"] {
+            assert!(dump.contains(&format!("\"registry test {}\"", label)));
+        }
+
+        drop((a, b, b2, c));
+
+        // The remaining weak reference keeps neither the value nor its registration alive.
+        assert!(w.upgrade().is_none());
+        assert!(!dump_all().contains("registry test"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Thread-local context for references created without an explicit site.
+//!
+//! Instead of annotating every reference, a label can be pushed for the duration of a scope. All
+//! references created on the same thread while the scope is active through methods that do not take
+//! a site, such as `Snarc::new`, `clone`, `Snarc::downgrade` or `Weak::upgrade`, as well as
+//! conversions like `From`, get a `Site::Annotated` carrying the label instead of the location of
+//! the caller:
+//!
+//! ```rust
+//! use snarc::{scope, Snarc};
+//!
+//! let _guard = scope::push("request-handler");
+//! let foo = Snarc::new(123);
+//! # #[cfg(feature = "tracking")]
+//! assert_eq!(
+//!     format!("{}", Snarc::origin(&foo).site),
+//!     "\"request-handler\""
+//! );
+//! ```
+//!
+//! Scopes nest, the resulting label joins the labels of all active scopes with ` > `, outermost
+//! first. Methods taking an explicit site, like `Snarc::new_at_line` or `Snarc::clone_annotated`,
+//! are not affected.
+//!
+//! Requires `std`, the module is unavailable with the `no_std` feature.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+thread_local! {
+    /// Labels of all active scopes on this thread, outermost first.
+    static STACK: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Guard of an active scope, see `push`.
+///
+/// Dropping the guard ends the scope, along with all scopes nested inside of it.
+#[derive(Debug)]
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct ScopeGuard {
+    /// Number of active scopes before this one was pushed.
+    depth: usize,
+    /// Scopes belong to a thread, so the guard must not be sent to another one.
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        STACK.with(|stack| stack.borrow_mut().truncate(self.depth));
+    }
+}
+
+/// Starts a new scope on the current thread, lasting until the returned guard is dropped.
+pub fn push(label: &str) -> ScopeGuard {
+    let depth = STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        stack.push(label.to_owned());
+        stack.len() - 1
+    });
+
+    ScopeGuard {
+        depth,
+        _thread: PhantomData,
+    }
+}
+
+/// Returns the label of the active scopes on the current thread, if any.
+pub fn current() -> Option<String> {
+    STACK.with(|stack| {
+        let stack = stack.borrow();
+        if stack.is_empty() {
+            None
+        } else {
+            Some(stack.join(" > "))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current, push};
+    use std::thread;
+
+    #[test]
+    fn nested_scopes() {
+        assert_eq!(current(), None);
+
+        let outer = push("outer");
+        assert_eq!(current().as_deref(), Some("outer"));
+
+        {
+            let _inner = push("inner");
+            assert_eq!(current().as_deref(), Some("outer > inner"));
+
+            // Other threads are not affected.
+            thread::spawn(|| assert_eq!(current(), None))
+                .join()
+                .unwrap();
+        }
+        assert_eq!(current().as_deref(), Some("outer"));
+
+        // Ending the outer scope ends the nested ones as well.
+        let _inner = push("inner");
+        drop(outer);
+        assert_eq!(current(), None);
+    }
+}
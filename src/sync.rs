@@ -0,0 +1,148 @@
+//! Locking primitives.
+//!
+//! Wraps either `std::sync::RwLock`, `parking_lot::RwLock` with the `parking_lot` feature, or
+//! `spin::RwLock` with the `no_std` feature behind a common interface.
+//!
+//! With the `contention` feature, every acquisition first tries to take the lock without blocking,
+//! counting how often that fails because another thread holds it.
+
+#[cfg(feature = "contention")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(all(feature = "parking_lot", not(feature = "no_std")))]
+use parking_lot::{RwLock as RawRwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "no_std")]
+use spin::{RwLock as RawRwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(not(feature = "no_std"))]
+use std::sync::LockResult;
+#[cfg(all(
+    feature = "contention",
+    not(any(feature = "no_std", feature = "parking_lot"))
+))]
+use std::sync::TryLockError;
+#[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+use std::sync::{PoisonError, RwLock as RawRwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A reader-writer lock.
+#[derive(Debug)]
+pub(crate) struct RwLock<T> {
+    /// The wrapped lock.
+    inner: RawRwLock<T>,
+    /// Number of acquisitions that had to wait for another thread to release the lock.
+    #[cfg(feature = "contention")]
+    contended: AtomicUsize,
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new, unlocked lock.
+    pub(crate) const fn new(value: T) -> RwLock<T> {
+        RwLock {
+            inner: RawRwLock::new(value),
+            #[cfg(feature = "contention")]
+            contended: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns how often acquiring the lock had to wait for another thread to release it.
+    #[cfg(feature = "contention")]
+    pub(crate) fn contended(&self) -> usize {
+        self.contended.load(Ordering::Relaxed)
+    }
+
+    /// Locks for shared read access, blocking until the lock is available.
+    ///
+    /// A poisoned lock is recovered from, as merely reading can not make matters worse.
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.checked_read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks for shared read access, blocking until the lock is available.
+    ///
+    /// Fails if the lock is poisoned, the guard can still be recovered from the error.
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    pub(crate) fn checked_read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        #[cfg(feature = "contention")]
+        match self.inner.try_read() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(err)) => return Err(err),
+            Err(TryLockError::WouldBlock) => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.read()
+    }
+
+    /// Locks for shared read access, blocking or spinning until the lock is available.
+    #[cfg(any(feature = "no_std", feature = "parking_lot"))]
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(feature = "contention")]
+        match self.inner.try_read() {
+            Some(guard) => return guard,
+            None => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.read()
+    }
+
+    /// Locks for shared read access, blocking until the lock is available.
+    ///
+    /// Never fails, as `parking_lot` locks are not poisoned.
+    #[cfg(all(feature = "parking_lot", not(feature = "no_std")))]
+    pub(crate) fn checked_read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        Ok(self.read())
+    }
+
+    /// Locks for exclusive write access, blocking until the lock is available.
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.checked_write()
+            .expect("Poisoned strong mapping. This is a bug.")
+    }
+
+    /// Locks for exclusive write access, blocking until the lock is available.
+    ///
+    /// Unlike `write`, a poisoned lock is recovered from. Meant for destructors, which must not
+    /// panic, as another panic while unwinding aborts the process.
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    pub(crate) fn write_recovering(&self) -> RwLockWriteGuard<'_, T> {
+        self.checked_write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks for exclusive write access, blocking until the lock is available.
+    ///
+    /// Fails if the lock is poisoned, the guard can still be recovered from the error.
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    fn checked_write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        #[cfg(feature = "contention")]
+        match self.inner.try_write() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(err)) => return Err(err),
+            Err(TryLockError::WouldBlock) => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.write()
+    }
+
+    /// Locks for exclusive write access, blocking or spinning until the lock is available.
+    #[cfg(any(feature = "no_std", feature = "parking_lot"))]
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        #[cfg(feature = "contention")]
+        match self.inner.try_write() {
+            Some(guard) => return guard,
+            None => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.write()
+    }
+
+    /// Locks for exclusive write access, blocking or spinning until the lock is available.
+    ///
+    /// The same as `write`, as these locks are never poisoned.
+    #[cfg(any(feature = "no_std", feature = "parking_lot"))]
+    pub(crate) fn write_recovering(&self) -> RwLockWriteGuard<'_, T> {
+        self.write()
+    }
+}
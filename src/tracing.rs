@@ -2,13 +2,43 @@
 //!
 //! Data types to track origin and history across call sites.
 
-use std::fmt;
+use alloc::collections::BTreeMap;
+#[cfg(feature = "no_std")]
+use alloc::string::String;
+use alloc::sync::Arc;
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter;
+use core::panic::Location;
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+#[cfg(not(feature = "no_std"))]
+use std::thread::{self, ThreadId};
+#[cfg(not(feature = "no_std"))]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 /// Unique ID type to identify ancestors.
+///
+/// A `usize`, unless the `u64-ids` feature is enabled. On 32-bit targets, the IDs of a long-lived
+/// value with a lot of reference churn may otherwise wrap around and repeat.
+#[cfg(not(feature = "u64-ids"))]
 pub type Uid = usize;
 
+/// Unique ID type to identify ancestors.
+///
+/// A `u64`, as the `u64-ids` feature is enabled.
+#[cfg(feature = "u64-ids")]
+pub type Uid = u64;
+
 /// Call site.
-#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum Site {
     /// File/line location inside a source file.
     SourceFile {
@@ -16,42 +46,227 @@ pub enum Site {
         file: &'static str,
         /// Line number, starting at 1.
         line: u32,
+        /// Column number, starting at 1, if known.
+        ///
+        /// Sites captured from the caller include the column, those passed in by file and line
+        /// (e.g. `Snarc::new_at_line`) do not.
+        column: Option<u32>,
     },
+    /// Path of the module containing the call site, as returned by `module_path!()`.
+    Module(&'static str),
     /// Unknown call site.
     ///
     /// Used, when no information about the original call site was available at runtime.
     Unknown,
-    // TODO: Backtrace,
+    /// Full stack trace of the call site.
+    #[cfg(feature = "backtrace")]
+    Backtrace(CapturedBacktrace),
+    /// User supplied label.
     Annotated(String),
 }
 
+impl Site {
+    /// Returns the location of the caller as a `Site::SourceFile`.
+    ///
+    /// Functions marked `#[track_caller]` calling this will report the location they were called
+    /// from instead.
+    #[track_caller]
+    pub fn caller() -> Site {
+        Site::from(Location::caller())
+    }
+}
+
+impl<'a> From<&'a Location<'static>> for Site {
+    /// Converts a location, e.g. one obtained by `Location::caller`, into a `Site::SourceFile`
+    /// including its column.
+    fn from(location: &'a Location<'static>) -> Site {
+        Site::SourceFile {
+            file: location.file(),
+            line: location.line(),
+            column: Some(location.column()),
+        }
+    }
+}
+
 impl fmt::Display for Site {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Site::SourceFile { file, line } => write!(f, "{}:{}", file, line),
+            Site::SourceFile {
+                file,
+                line,
+                column: None,
+            } => write!(f, "{}:{}", file, line),
+            Site::SourceFile {
+                file,
+                line,
+                column: Some(column),
+            } => write!(f, "{}:{}:{}", file, line, column),
+            Site::Module(path) => write!(f, "mod:{}", path),
             Site::Unknown => write!(f, "?"),
+            #[cfg(feature = "backtrace")]
+            Site::Backtrace(ref bt) => write!(f, "backtrace:\n{}", bt),
             Site::Annotated(ref s) => write!(f, "\"{}\"", s),
         }
     }
 }
 
+/// A captured stack trace.
+///
+/// Wraps a `std::backtrace::Backtrace`, which is neither cloneable nor comparable, to allow using it
+/// as a `Site`. Clones share the same trace; comparisons are made on the rendered frames.
+#[cfg(feature = "backtrace")]
+#[derive(Debug, Clone)]
+pub struct CapturedBacktrace(Arc<Backtrace>);
+
+#[cfg(feature = "backtrace")]
+impl CapturedBacktrace {
+    /// Captures the current stack trace.
+    ///
+    /// See `std::backtrace::Backtrace::capture` for details, especially regarding the environment
+    /// variables that need to be set for a trace to actually be captured.
+    pub fn capture() -> CapturedBacktrace {
+        CapturedBacktrace(Arc::new(Backtrace::capture()))
+    }
+
+    /// Returns the wrapped backtrace.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.0
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl fmt::Display for CapturedBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Serializes the rendered frames.
+#[cfg(all(feature = "backtrace", feature = "serde"))]
+impl Serialize for CapturedBacktrace {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl PartialEq for CapturedBacktrace {
+    fn eq(&self, other: &CapturedBacktrace) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.to_string() == other.0.to_string()
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl Eq for CapturedBacktrace {}
+
+#[cfg(feature = "backtrace")]
+impl Hash for CapturedBacktrace {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_string().hash(state)
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl PartialOrd for CapturedBacktrace {
+    fn partial_cmp(&self, other: &CapturedBacktrace) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl Ord for CapturedBacktrace {
+    fn cmp(&self, other: &CapturedBacktrace) -> Ordering {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return Ordering::Equal;
+        }
+        self.0.to_string().cmp(&other.0.to_string())
+    }
+}
+
 /// Reference origin.
+///
+/// The origin of the parent reference is shared, cloning an `OriginKind` (or the `Origin`
+/// containing it) does not copy the chain of ancestors.
 #[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum OriginKind {
     /// New object Instantiation (resulting ID),
     New,
     // FIXME: IDs need to be for current, not passed down.
     // FIXME: Move ID into Origin.
     /// Cloned from another reference, (original ID, site of original reference).
-    Cloned(Box<Origin>),
+    Cloned(Arc<Origin>),
     /// Upgraded from a weak reference, (weak reference ID, site of weak reference).
-    Upgraded(Box<Origin>),
+    Upgraded(Arc<Origin>),
     /// Downgraded from a strong reference, (strong reference ID, site of strong reference).
-    Downgraded(Box<Origin>),
+    Downgraded(Arc<Origin>),
+}
+
+impl OriginKind {
+    /// Returns the name of the operation, as used when formatting origins.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OriginKind::New => "new",
+            OriginKind::Cloned(_) => "clone",
+            OriginKind::Upgraded(_) => "upgrade",
+            OriginKind::Downgraded(_) => "downgrade",
+        }
+    }
+
+    /// Returns the origin of the parent reference, if any.
+    pub fn parent(&self) -> Option<&Origin> {
+        match *self {
+            OriginKind::New => None,
+            OriginKind::Cloned(ref parent)
+            | OriginKind::Upgraded(ref parent)
+            | OriginKind::Downgraded(ref parent) => Some(parent),
+        }
+    }
+}
+
+/// Number of references by the kind of their origin, see `Snarc::kind_counts`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct KindCounts {
+    /// References created by `OriginKind::New`.
+    pub new: usize,
+    /// References created by `OriginKind::Cloned`.
+    pub cloned: usize,
+    /// References created by `OriginKind::Upgraded`.
+    pub upgraded: usize,
+    /// References created by `OriginKind::Downgraded`.
+    pub downgraded: usize,
+}
+
+impl KindCounts {
+    /// Counts one more reference of the given kind.
+    pub fn add(&mut self, kind: &OriginKind) {
+        *match *kind {
+            OriginKind::New => &mut self.new,
+            OriginKind::Cloned(_) => &mut self.cloned,
+            OriginKind::Upgraded(_) => &mut self.upgraded,
+            OriginKind::Downgraded(_) => &mut self.downgraded,
+        } += 1;
+    }
 }
 
 /// Describes origin and location of a new reference creation.
-#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+///
+/// Origins are compared, ordered and hashed by ID alone, which identifies a reference within its
+/// family, so none of these operations walk the chain of ancestors. Origins from different
+/// families, or from a family recycling IDs (see `Snarc::new_with_recycled_ids`), may compare equal
+/// despite describing different references.
+///
+/// When formatted using the alternate flag (`{:#}`), the time elapsed since the creation of each
+/// link in the chain is included.
+///
+/// With the `serde` feature enabled, origins can be serialized. The thread is serialized using its
+/// debug representation, while the creation and drop times are omitted, as they are meaningless
+/// outside of the running process.
+///
+/// With the `no_std` feature enabled, neither the thread nor any times are recorded.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Origin {
     /// The resulting ID of the instantiation.
     pub id: Uid,
@@ -60,111 +275,633 @@ pub struct Origin {
     /// The kind of reference creation (new, via clone, downgrade, ...). In case there is a parent
     /// instance, its origin information will be contained in the `OriginKind` instance.
     pub kind: OriginKind,
+    /// The thread the new instantiation occured on.
+    #[cfg(not(feature = "no_std"))]
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_thread"))]
+    pub thread: ThreadId,
+    /// The point in time the new instantiation occured at.
+    #[cfg(not(feature = "no_std"))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub created_at: Instant,
+    /// The point in time the reference was dropped at, if it has been dropped already.
+    ///
+    /// Only set on origins retained in the history of a family, see `Snarc::new_with_history`.
+    #[cfg(not(feature = "no_std"))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub dropped_at: Option<Instant>,
+}
+
+impl Origin {
+    /// Creates a new origin, recording the current thread and time.
+    pub fn new(id: Uid, site: Site, kind: OriginKind) -> Origin {
+        Origin {
+            id,
+            site,
+            kind,
+            #[cfg(not(feature = "no_std"))]
+            thread: thread::current().id(),
+            #[cfg(not(feature = "no_std"))]
+            created_at: Instant::now(),
+            #[cfg(not(feature = "no_std"))]
+            dropped_at: None,
+        }
+    }
+
+    /// Returns the time elapsed since the instantiation occured.
+    #[cfg(not(feature = "no_std"))]
+    pub fn elapsed(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// Returns an iterator over the origin chain.
+    ///
+    /// Yields the origin itself first, followed by each of its ancestors, ending with the
+    /// `OriginKind::New` origin the chain started with.
+    pub fn ancestors(&self) -> impl Iterator<Item = &Origin> {
+        iter::successors(Some(self), |link| link.kind.parent())
+    }
+
+    /// Returns the number of links between this origin and the `OriginKind::New` it started with.
+    ///
+    /// A `New` origin itself has a depth of 0.
+    pub fn depth(&self) -> usize {
+        self.ancestors().count() - 1
+    }
+
+    /// Returns the `OriginKind::New` origin the chain started with.
+    pub fn root(&self) -> &Origin {
+        self.ancestors()
+            .last()
+            .expect("Origin chain without any links. This should never happen.")
+    }
+
+    /// Returns whether the reference with the given ID is among the ancestors of this origin.
+    ///
+    /// Only the parent chain is searched, an origin is not a descendant of itself.
+    pub fn is_descendant_of(&self, id: Uid) -> bool {
+        self.ancestors().skip(1).any(|link| link.id == id)
+    }
+
+    /// Returns the ID of the nearest origin both `a` and `b` descend from.
+    ///
+    /// The origins themselves are included in the search, so if `b` descends from `a`, the ID of
+    /// `a` is returned. Both origins should belong to the same family, as IDs are only unique within
+    /// one.
+    pub fn common_ancestor(a: &Origin, b: &Origin) -> Option<Uid> {
+        a.ancestors()
+            .map(|link| link.id)
+            .find(|&id| b.ancestors().any(|other| other.id == id))
+    }
+}
+
+/// Serializes a `ThreadId` using its debug representation.
+#[cfg(all(feature = "serde", not(feature = "no_std")))]
+fn serialize_thread<S: serde::Serializer>(
+    thread: &ThreadId,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", thread))
+}
+
+impl PartialEq for Origin {
+    fn eq(&self, other: &Origin) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Origin {}
+
+impl Hash for Origin {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl PartialOrd for Origin {
+    fn partial_cmp(&self, other: &Origin) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Origin {
+    fn cmp(&self, other: &Origin) -> Ordering {
+        self.id.cmp(&other.id)
+    }
 }
 
 impl fmt::Display for Origin {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut cur = Some(self);
-
-        while let Some(link) = cur {
-            match link.kind {
-                OriginKind::New => {
-                    write!(f, "new<{}>[{}]", link.id, link.site)?;
-                    cur = None;
-                }
-                OriginKind::Cloned(ref parent) => {
-                    write!(f, "clone<{}>[{}]", link.id, link.site)?;
-                    cur = Some(parent);
-                }
-                OriginKind::Upgraded(ref parent) => {
-                    write!(f, "upgrade<{}>[{}]", link.id, link.site)?;
-                    cur = Some(parent);
-                }
-                OriginKind::Downgraded(ref parent) => {
-                    write!(f, "downgrade<{}>[{}]", link.id, link.site)?;
-                    cur = Some(parent);
-                }
-            };
-
-            if cur.is_some() {
+        for (idx, link) in self.ancestors().enumerate() {
+            if idx > 0 {
                 write!(f, " <- ")?;
             }
+
+            fmt::Display::fmt(&Link(link), f)?;
         }
 
         Ok(())
     }
 }
 
+/// Output helper for a single origin, without its ancestors.
+///
+/// Formats like the first link of the chain printed for an `Origin`, including the elapsed time if
+/// the alternate flag is set.
+pub(crate) struct Link<'a>(pub(crate) &'a Origin);
+
+impl<'a> fmt::Display for Link<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let link = self.0;
+        write!(f, "{}<{}>[{}]", link.kind.name(), link.id, link.site)?;
+
+        #[cfg(not(feature = "no_std"))]
+        write!(f, "{{{:?}}}", link.thread)?;
+
+        #[cfg(not(feature = "no_std"))]
+        if f.alternate() {
+            write!(f, " ({:?} ago)", link.elapsed())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Origins of a family, arranged by descent, see `Snarc::family_tree`.
+///
+/// Besides all live references, the tree contains the dropped references they descend from, as far
+/// as they are recorded in the origin chains. Each reference is a child of the one it was cloned,
+/// upgraded or downgraded from, the root is the `OriginKind::New` origin of the family.
+#[derive(Debug, Clone, Default)]
+pub struct FamilyTree {
+    /// All nodes of the tree, by ID.
+    nodes: BTreeMap<Uid, Node>,
+    /// ID of the root node, absent only if the tree is empty.
+    root: Option<Uid>,
+}
+
+/// A single reference in a `FamilyTree`.
+#[derive(Debug, Clone)]
+struct Node {
+    origin: Origin,
+    /// Whether the reference is still alive, as opposed to being a dropped ancestor.
+    live: bool,
+    /// IDs of the references created from this one, in ascending order.
+    children: Vec<Uid>,
+}
+
+impl FamilyTree {
+    /// Arranges the given strong and weak origins, as returned by `Snarc::family`, in a tree.
+    pub fn new((strongs, weaks): (Vec<Origin>, Vec<Origin>)) -> FamilyTree {
+        let node = |origin: &Origin, live| Node {
+            origin: origin.clone(),
+            live,
+            children: Vec::new(),
+        };
+
+        // Live references first, so that they are not mistaken for dead ancestors.
+        let mut nodes: BTreeMap<Uid, Node> = BTreeMap::new();
+        for origin in strongs.iter().chain(weaks.iter()) {
+            nodes.insert(origin.id, node(origin, true));
+        }
+        for origin in strongs.iter().chain(weaks.iter()) {
+            for ancestor in origin.ancestors().skip(1) {
+                nodes
+                    .entry(ancestor.id)
+                    .or_insert_with(|| node(ancestor, false));
+            }
+        }
+
+        // Iterating in ID order keeps the children sorted.
+        let edges: Vec<_> = nodes
+            .values()
+            .filter_map(|node| Some((node.origin.kind.parent()?.id, node.origin.id)))
+            .collect();
+        for (parent, child) in edges {
+            if let Some(node) = nodes.get_mut(&parent) {
+                node.children.push(child);
+            }
+        }
+
+        let root = nodes
+            .values()
+            .find(|node| node.origin.kind.parent().is_none())
+            .map(|node| node.origin.id);
+
+        FamilyTree { nodes, root }
+    }
+
+    /// Returns the `OriginKind::New` origin of the family, if the tree is not empty.
+    pub fn root(&self) -> Option<&Origin> {
+        self.root.map(|id| &self.nodes[&id].origin)
+    }
+
+    /// Returns the origin of the reference with the given ID, if it is part of the tree.
+    pub fn get(&self, id: Uid) -> Option<&Origin> {
+        self.nodes.get(&id).map(|node| &node.origin)
+    }
+
+    /// Returns whether the reference with the given ID is alive, rather than a dropped ancestor or
+    /// not part of the tree at all.
+    pub fn is_live(&self, id: Uid) -> bool {
+        self.nodes.get(&id).is_some_and(|node| node.live)
+    }
+
+    /// Returns the origins of the references directly created from the one with the given ID, in
+    /// ascending order of their IDs.
+    pub fn children(&self, id: Uid) -> impl Iterator<Item = &Origin> {
+        self.nodes
+            .get(&id)
+            .into_iter()
+            .flat_map(|node| node.children.iter())
+            .map(move |child| &self.nodes[child].origin)
+    }
+
+    /// Returns the number of references in the tree, live and dropped ones.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns whether the tree contains no references at all.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns a depth-first iterator over the tree, starting at the root.
+    ///
+    /// Each reference is yielded before its children, which are visited in ascending order of
+    /// their IDs. The depth of each yielded origin within the tree is `Origin::depth`.
+    pub fn iter(&self) -> DepthFirst<'_> {
+        DepthFirst {
+            tree: self,
+            stack: self.root.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a FamilyTree {
+    type Item = &'a Origin;
+    type IntoIter = DepthFirst<'a>;
+
+    fn into_iter(self) -> DepthFirst<'a> {
+        self.iter()
+    }
+}
+
+/// Depth-first iterator over a `FamilyTree`, see `FamilyTree::iter`.
+#[derive(Debug, Clone)]
+pub struct DepthFirst<'a> {
+    tree: &'a FamilyTree,
+    /// IDs of the references yet to be visited, the next one last.
+    stack: Vec<Uid>,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = &'a Origin;
+
+    fn next(&mut self) -> Option<&'a Origin> {
+        let node = &self.tree.nodes[&self.stack.pop()?];
+        self.stack.extend(node.children.iter().rev());
+        Some(&node.origin)
+    }
+}
+
+/// The live references of a family at one point in time, see `Snarc::snapshot`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FamilySnapshot {
+    strongs: BTreeMap<Uid, Origin>,
+    weaks: BTreeMap<Uid, Origin>,
+}
+
+/// Changes between two snapshots of a family, see `FamilySnapshot::diff`.
+///
+/// All origins are sorted by ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FamilyDiff {
+    /// Strong references created in between.
+    pub added_strongs: Vec<Origin>,
+    /// Strong references dropped in between.
+    pub removed_strongs: Vec<Origin>,
+    /// Weak references created in between.
+    pub added_weaks: Vec<Origin>,
+    /// Weak references dropped in between.
+    pub removed_weaks: Vec<Origin>,
+}
+
+impl FamilySnapshot {
+    /// Captures the given strong and weak origins, as returned by `Snarc::family`.
+    pub fn new((strongs, weaks): (Vec<Origin>, Vec<Origin>)) -> FamilySnapshot {
+        let by_id = |origins: Vec<Origin>| {
+            origins
+                .into_iter()
+                .map(|origin| (origin.id, origin))
+                .collect()
+        };
+
+        FamilySnapshot {
+            strongs: by_id(strongs),
+            weaks: by_id(weaks),
+        }
+    }
+
+    /// Returns the origins of the strong references, sorted by ID.
+    pub fn strongs(&self) -> impl Iterator<Item = &Origin> {
+        self.strongs.values()
+    }
+
+    /// Returns the origins of the weak references, sorted by ID.
+    pub fn weaks(&self) -> impl Iterator<Item = &Origin> {
+        self.weaks.values()
+    }
+
+    /// Returns the references created and dropped between this snapshot and a later one.
+    ///
+    /// A reference created and dropped again in between appears in neither snapshot and is not
+    /// reported.
+    pub fn diff(&self, later: &FamilySnapshot) -> FamilyDiff {
+        /// Returns the origins in `a` with an ID not found in `b`.
+        fn missing(a: &BTreeMap<Uid, Origin>, b: &BTreeMap<Uid, Origin>) -> Vec<Origin> {
+            a.values()
+                .filter(|origin| !b.contains_key(&origin.id))
+                .cloned()
+                .collect()
+        }
+
+        FamilyDiff {
+            added_strongs: missing(&later.strongs, &self.strongs),
+            removed_strongs: missing(&self.strongs, &later.strongs),
+            added_weaks: missing(&later.weaks, &self.weaks),
+            removed_weaks: missing(&self.weaks, &later.weaks),
+        }
+    }
+}
+
+impl FamilyDiff {
+    /// Returns whether no references were created or dropped.
+    pub fn is_empty(&self) -> bool {
+        self.added_strongs.is_empty()
+            && self.removed_strongs.is_empty()
+            && self.added_weaks.is_empty()
+            && self.removed_weaks.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Origin, OriginKind, Site};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    #[cfg(not(feature = "no_std"))]
+    use std::thread;
 
+    #[cfg(not(feature = "no_std"))]
     #[test]
     fn format_origin_single() {
-        let subj = Origin {
-            kind: OriginKind::New,
-            site: Site::Unknown,
-            id: 15,
-        };
+        let subj = Origin::new(15, Site::Unknown, OriginKind::New);
+
+        let thread = format!("{{{:?}}}", thread::current().id());
 
-        assert_eq!("new<15>[?]".to_string(), format!("{}", subj));
+        assert_eq!(format!("new<15>[?]{}", thread), format!("{}", subj));
 
-        let subj = Origin {
-            kind: OriginKind::New,
-            site: Site::SourceFile {
+        let subj = Origin::new(
+            123,
+            Site::SourceFile {
                 file: "foo.rs",
                 line: 543,
+                column: None,
             },
-            id: 123,
-        };
+            OriginKind::New,
+        );
 
-        assert_eq!("new<123>[foo.rs:543]".to_string(), format!("{}", subj));
+        assert_eq!(
+            format!("new<123>[foo.rs:543]{}", thread),
+            format!("{}", subj)
+        );
 
-        let subj = Origin {
-            kind: OriginKind::New,
-            site: Site::Annotated("dummy".to_string()),
-            id: 0,
-        };
+        let subj = Origin::new(
+            124,
+            Site::SourceFile {
+                file: "foo.rs",
+                line: 543,
+                column: Some(9),
+            },
+            OriginKind::New,
+        );
+
+        assert_eq!(
+            format!("new<124>[foo.rs:543:9]{}", thread),
+            format!("{}", subj)
+        );
+
+        let subj = Origin::new(0, Site::Annotated("dummy".to_string()), OriginKind::New);
+
+        assert_eq!(format!("new<0>[\"dummy\"]{}", thread), format!("{}", subj));
+
+        let subj = Origin::new(1, Site::Module("foo::bar"), OriginKind::New);
 
-        assert_eq!("new<0>[\"dummy\"]".to_string(), format!("{}", subj));
+        assert_eq!(
+            format!("new<1>[mod:foo::bar]{}", thread),
+            format!("{}", subj)
+        );
     }
 
+    #[cfg(not(feature = "no_std"))]
     #[test]
     fn format_origin_chain() {
-        let one = Origin {
-            kind: OriginKind::New,
-            site: Site::SourceFile {
+        let one = Origin::new(
+            0,
+            Site::SourceFile {
                 file: "orig.rs",
                 line: 999,
+                column: None,
             },
-            id: 0,
-        };
+            OriginKind::New,
+        );
 
-        let two = Origin {
-            kind: OriginKind::Cloned(Box::new(one)),
-            site: Site::Annotated("step two".to_string()),
-            id: 1,
-        };
+        let two = Origin::new(
+            1,
+            Site::Annotated("step two".to_string()),
+            OriginKind::Cloned(Arc::new(one)),
+        );
 
-        let three = Origin {
-            kind: OriginKind::Downgraded(Box::new(two)),
-            site: Site::Unknown,
-            id: 2,
-        };
+        let three = Origin::new(2, Site::Unknown, OriginKind::Downgraded(Arc::new(two)));
 
-        let four = Origin {
-            kind: OriginKind::Upgraded(Box::new(three)),
-            site: Site::SourceFile {
+        let four = Origin::new(
+            3,
+            Site::SourceFile {
                 file: "final.rs",
                 line: 42,
+                column: None,
             },
-            id: 3,
-        };
+            OriginKind::Upgraded(Arc::new(three)),
+        );
 
         assert_eq!(
-            "upgrade<3>[final.rs:42] <- downgrade<2>[?] \
-             <- clone<1>[\"step two\"] <- new<0>[orig.rs:999]",
+            format!(
+                "upgrade<3>[final.rs:42]{{{0:?}}} <- downgrade<2>[?]{{{0:?}}} \
+                 <- clone<1>[\"step two\"]{{{0:?}}} <- new<0>[orig.rs:999]{{{0:?}}}",
+                thread::current().id()
+            ),
             format!("{}", four)
         );
     }
+
+    #[cfg(feature = "no_std")]
+    #[test]
+    fn format_origin_without_context() {
+        let one = Origin::new(0, Site::Unknown, OriginKind::New);
+        let two = Origin::new(
+            1,
+            Site::Annotated("two".to_string()),
+            OriginKind::Cloned(Arc::new(one)),
+        );
+
+        assert_eq!("clone<1>[\"two\"] <- new<0>[?]", format!("{}", two));
+        assert_eq!(format!("{}", two), format!("{:#}", two));
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn format_backtrace() {
+        use super::CapturedBacktrace;
+
+        let site = Site::Backtrace(CapturedBacktrace::capture());
+        let formatted = format!("{}", site);
+
+        assert!(formatted.starts_with("backtrace:\n"));
+        assert!(formatted.len() > "backtrace:\n".len());
+        assert_eq!(site.clone(), site);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn format_origin_elapsed() {
+        let one = Origin::new(0, Site::Unknown, OriginKind::New);
+        let two = Origin::new(1, Site::Unknown, OriginKind::Cloned(Arc::new(one)));
+
+        let formatted = format!("{:#}", two);
+        assert_eq!(formatted.matches(" ago)").count(), 2);
+        assert!(!format!("{}", two).contains(" ago)"));
+    }
+
+    #[test]
+    fn ancestors() {
+        let one = Origin::new(0, Site::Unknown, OriginKind::New);
+        let two = Origin::new(1, Site::Unknown, OriginKind::Cloned(Arc::new(one)));
+        let three = Origin::new(2, Site::Unknown, OriginKind::Downgraded(Arc::new(two)));
+        let four = Origin::new(3, Site::Unknown, OriginKind::Upgraded(Arc::new(three)));
+
+        let ids: Vec<_> = four.ancestors().map(|link| link.id).collect();
+        assert_eq!(ids, vec![3, 2, 1, 0]);
+
+        let root = Origin::new(7, Site::Unknown, OriginKind::New);
+        assert_eq!(root.ancestors().count(), 1);
+    }
+
+    #[test]
+    fn depth_and_root() {
+        let one = Origin::new(0, Site::Unknown, OriginKind::New);
+        let two = Origin::new(1, Site::Unknown, OriginKind::Cloned(Arc::new(one.clone())));
+        let three = Origin::new(2, Site::Unknown, OriginKind::Downgraded(Arc::new(two)));
+        let four = Origin::new(3, Site::Unknown, OriginKind::Upgraded(Arc::new(three)));
+
+        assert_eq!(one.depth(), 0);
+        assert_eq!(four.depth(), 3);
+
+        assert_eq!(four.root(), &one);
+        assert_eq!(four.root().kind, OriginKind::New);
+        assert_eq!(one.root(), &one);
+    }
+
+    #[test]
+    fn ancestry() {
+        // a -> b -> d, a -> c
+        let a = Arc::new(Origin::new(0, Site::Unknown, OriginKind::New));
+        let b = Arc::new(Origin::new(1, Site::Unknown, OriginKind::Cloned(a.clone())));
+        let c = Origin::new(2, Site::Unknown, OriginKind::Cloned(a.clone()));
+        let d = Origin::new(3, Site::Unknown, OriginKind::Downgraded(b.clone()));
+
+        assert!(b.is_descendant_of(0));
+        assert!(d.is_descendant_of(0));
+        assert!(d.is_descendant_of(1));
+        assert!(!c.is_descendant_of(1));
+        assert!(!a.is_descendant_of(0));
+
+        assert_eq!(Origin::common_ancestor(&b, &c), Some(0));
+        assert_eq!(Origin::common_ancestor(&d, &c), Some(0));
+        assert_eq!(Origin::common_ancestor(&d, &b), Some(1));
+        assert_eq!(Origin::common_ancestor(&c, &c), Some(2));
+
+        let other = Origin::new(7, Site::Unknown, OriginKind::New);
+        assert_eq!(Origin::common_ancestor(&b, &other), None);
+    }
+
+    #[test]
+    fn id_equality() {
+        let a = Arc::new(Origin::new(0, Site::Unknown, OriginKind::New));
+        let b = Origin::new(
+            1,
+            Site::Annotated("b".to_owned()),
+            OriginKind::Cloned(a.clone()),
+        );
+        let c = Origin::new(
+            2,
+            Site::Annotated("b".to_owned()),
+            OriginKind::Cloned(a.clone()),
+        );
+        let b_again = Origin::new(1, Site::Unknown, OriginKind::Downgraded(a.clone()));
+
+        // Only the ID matters, not the site, kind or chain.
+        assert_ne!(b, c);
+        assert_eq!(b, b_again);
+        assert!(b < c);
+
+        let set: HashSet<Origin> = vec![(*a).clone(), b, c, b_again].into_iter().collect();
+        let mut ids: Vec<_> = set.iter().map(|origin| origin.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_origin_chain() {
+        use serde_json::{self, Value};
+
+        let one = Origin::new(
+            0,
+            Site::SourceFile {
+                file: "orig.rs",
+                line: 999,
+                column: None,
+            },
+            OriginKind::New,
+        );
+        let two = Origin::new(
+            1,
+            Site::Annotated("step two".to_string()),
+            OriginKind::Cloned(Arc::new(one)),
+        );
+        let three = Origin::new(2, Site::Unknown, OriginKind::Downgraded(Arc::new(two)));
+
+        let json = serde_json::to_value(&three).unwrap();
+
+        assert_eq!(json["id"], 2);
+        assert_eq!(json["site"], "Unknown");
+        #[cfg(not(feature = "no_std"))]
+        assert_eq!(json["thread"], format!("{:?}", thread::current().id()));
+        assert!(json.get("created_at").is_none());
+
+        let two = &json["kind"]["Downgraded"];
+        assert_eq!(two["id"], 1);
+        assert_eq!(two["site"]["Annotated"], "step two");
+
+        let one = &two["kind"]["Cloned"];
+        assert_eq!(one["id"], 0);
+        assert_eq!(one["site"]["SourceFile"]["file"], "orig.rs");
+        assert_eq!(one["site"]["SourceFile"]["line"], 999);
+        assert_eq!(one["kind"], Value::String("New".to_string()));
+
+        // The serialized form is stable, allowing it to be parsed back.
+        let text = serde_json::to_string(&three).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), json);
+    }
 }
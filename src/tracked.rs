@@ -0,0 +1,4422 @@
+//! Tracking implementation of `Snarc` and `Weak`, used if the `tracking` feature is enabled.
+
+#[cfg(feature = "no_std")]
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+#[cfg(feature = "no_std")]
+use alloc::format;
+#[cfg(feature = "no_std")]
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak as ArcWeak};
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+use core::any::Any;
+use core::borrow;
+use core::fmt;
+#[cfg(feature = "nightly")]
+use core::marker::Unsize;
+use core::mem::{self, ManuallyDrop};
+#[cfg(feature = "nightly")]
+use core::ops::CoerceUnsized;
+use core::ops::{Deref, DerefMut, Range};
+use core::panic::Location;
+use core::pin::Pin;
+use core::ptr;
+#[cfg(any(
+    feature = "u64-ids",
+    all(feature = "track-access", target_has_atomic = "64")
+))]
+use core::sync::atomic::AtomicU64;
+#[cfg(any(
+    not(feature = "u64-ids"),
+    all(feature = "track-access", not(target_has_atomic = "64"))
+))]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicPtr, Ordering};
+#[cfg(not(feature = "no_std"))]
+use std::collections::{HashMap, HashSet};
+#[cfg(not(feature = "no_std"))]
+use std::io;
+#[cfg(not(feature = "no_std"))]
+use std::sync::PoisonError;
+#[cfg(not(feature = "no_std"))]
+use std::thread::ThreadId;
+#[cfg(not(feature = "no_std"))]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "registry")]
+use registry;
+#[cfg(not(feature = "no_std"))]
+use scope;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use sync::RwLock;
+#[cfg(feature = "backtrace")]
+use tracing::CapturedBacktrace;
+use tracing::{FamilySnapshot, FamilyTree, KindCounts, Origin, OriginKind, Site, Uid};
+#[cfg(not(feature = "no_std"))]
+use wait_for;
+use {assert_counts, write_dump, DumpFilter, RefHook, SharedError, SnarcRef};
+
+/// Origins of tracked references, by ID.
+#[cfg(not(any(feature = "no_std", feature = "ordered")))]
+type Origins = HashMap<Uid, Origin>;
+/// Origins of tracked references, by ID, in ascending order.
+#[cfg(any(feature = "no_std", feature = "ordered"))]
+type Origins = BTreeMap<Uid, Origin>;
+
+/// Tracked reference state.
+///
+/// The `Map` tracks the number and site of references pointing toward the same value.
+#[derive(Debug)]
+struct Map {
+    strongs: Origins,
+    weaks: Origins,
+    /// Origins of dropped references, if history is retained, oldest first.
+    history: Option<VecDeque<Origin>>,
+    /// Maximum number of origins retained in `history`.
+    history_limit: usize,
+    /// Receiver of lifecycle events.
+    hook: Option<Box<dyn RefHook>>,
+    /// Number of failed upgrades by weak reference ID, only for those that failed at least once.
+    failed_upgrades: BTreeMap<Uid, usize>,
+    /// Whether the live references have spanned more than one thread at any point.
+    #[cfg(not(feature = "no_std"))]
+    shared: bool,
+    /// Only every this many derived references record their site, see `Snarc::new_sampled`.
+    sample_rate: usize,
+    /// Number of references derived so far, sampled or not.
+    derived: usize,
+    /// Highest number of strong references alive at the same time so far.
+    peak_strongs: usize,
+    /// Highest number of weak references alive at the same time so far.
+    peak_weaks: usize,
+    /// IDs of dropped references available for reuse, if IDs are recycled.
+    free_ids: Option<BTreeSet<Uid>>,
+}
+
+/// Number of strong and weak references each a `Map` has room for initially, unless specified.
+const DEFAULT_CAPACITY: usize = 4;
+
+impl Map {
+    /// Creates a new map instance, with room for `capacity` strong and weak references each.
+    ///
+    /// The capacity is ignored if the origins are kept in a `BTreeMap`.
+    #[cfg_attr(any(feature = "no_std", feature = "ordered"), allow(unused_variables))]
+    fn new(capacity: usize) -> Map {
+        Map {
+            #[cfg(not(any(feature = "no_std", feature = "ordered")))]
+            strongs: HashMap::with_capacity(capacity),
+            #[cfg(not(any(feature = "no_std", feature = "ordered")))]
+            weaks: HashMap::with_capacity(capacity),
+            #[cfg(any(feature = "no_std", feature = "ordered"))]
+            strongs: BTreeMap::new(),
+            #[cfg(any(feature = "no_std", feature = "ordered"))]
+            weaks: BTreeMap::new(),
+            history: None,
+            history_limit: usize::MAX,
+            hook: None,
+            failed_upgrades: BTreeMap::new(),
+            #[cfg(not(feature = "no_std"))]
+            shared: false,
+            sample_rate: 1,
+            derived: 0,
+            peak_strongs: 0,
+            peak_weaks: 0,
+            free_ids: None,
+        }
+    }
+
+    /// Creates a new map containing a single `OriginKind::New` origin at `site`.
+    ///
+    /// Returns the map and the ID assigned to the new origin.
+    fn seeded(site: Site) -> (Map, Uid) {
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        let id = map.seed(site);
+        (map, id)
+    }
+
+    /// Adds the `OriginKind::New` origin at `site` to an otherwise unused map.
+    ///
+    /// Returns the ID assigned to the new origin, which is always the first one, `0`.
+    fn seed(&mut self, site: Site) -> Uid {
+        let id = 0;
+        self.insert_strong(Origin::new(id, site, OriginKind::New));
+        id
+    }
+
+    /// Returns the site to record for a reference derived from an existing one.
+    ///
+    /// Unless the reference is sampled, its site is replaced with `Site::Unknown`, or the one
+    /// returned by the default site provider, see `set_default_site_provider`.
+    fn sample(&mut self, site: Site) -> Site {
+        let sampled = self.derived.is_multiple_of(self.sample_rate);
+        self.derived = self.derived.wrapping_add(1);
+
+        if sampled {
+            site
+        } else {
+            provided()
+        }
+    }
+
+    /// Adds the origin of a new strong reference.
+    fn insert_strong(&mut self, origin: Origin) {
+        self.created(&origin);
+        self.strongs.insert(origin.id, origin);
+        self.peak_strongs = self.peak_strongs.max(self.strongs.len());
+    }
+
+    /// Adds the origin of a new weak reference.
+    fn insert_weak(&mut self, origin: Origin) {
+        self.created(&origin);
+        self.weaks.insert(origin.id, origin);
+        self.peak_weaks = self.peak_weaks.max(self.weaks.len());
+    }
+
+    /// Notifies the hook about a new reference, before its origin is added.
+    fn created(&mut self, origin: &Origin) {
+        // Until the family is shared, all live references are on the same thread, so comparing
+        // against any one of them suffices.
+        #[cfg(not(feature = "no_std"))]
+        let newly_shared = !self.shared
+            && self
+                .strongs
+                .values()
+                .chain(self.weaks.values())
+                .next()
+                .is_some_and(|other| other.thread != origin.thread);
+        #[cfg(not(feature = "no_std"))]
+        {
+            self.shared |= newly_shared;
+        }
+
+        if let Some(ref hook) = self.hook {
+            hook.on_create(origin);
+            #[cfg(not(feature = "no_std"))]
+            {
+                if newly_shared {
+                    hook.on_shared(origin);
+                }
+            }
+        }
+    }
+
+    /// Removes the origin of a dropped strong reference.
+    ///
+    /// Returns `None` if no such reference was tracked.
+    fn remove_strong(&mut self, id: Uid) -> Option<()> {
+        let origin = self.strongs.remove(&id)?;
+        self.retire(origin);
+        Some(())
+    }
+
+    /// Removes the origin of a dropped weak reference.
+    ///
+    /// Returns `None` if no such reference was tracked.
+    fn remove_weak(&mut self, id: Uid) -> Option<()> {
+        let origin = self.weaks.remove(&id)?;
+        self.failed_upgrades.remove(&id);
+        self.retire(origin);
+        Some(())
+    }
+
+    /// Returns the origin of the strong reference with the given ID.
+    fn origin(&self, id: Uid) -> Origin {
+        self.strongs
+            .get(&id)
+            .expect("Internal consistency error (origin). This is a bug.")
+            .clone()
+    }
+
+    /// Returns the origins of all strong and weak references.
+    fn family(&self) -> (Vec<Origin>, Vec<Origin>) {
+        (
+            self.strongs.values().cloned().collect(),
+            self.weaks.values().cloned().collect(),
+        )
+    }
+
+    /// Describes what is known about the reference with the given ID, for use in error messages.
+    ///
+    /// Live references are looked up first, followed by the history of dropped ones, if retained.
+    fn describe(&self, id: Uid) -> String {
+        let found = self
+            .strongs
+            .get(&id)
+            .map(|origin| ("strong", origin))
+            .or_else(|| self.weaks.get(&id).map(|origin| ("weak", origin)))
+            .or_else(|| {
+                self.history
+                    .iter()
+                    .flatten()
+                    .rev()
+                    .find(|origin| origin.id == id)
+                    .map(|origin| ("dropped", origin))
+            });
+
+        match found {
+            Some((state, origin)) => format!("reference {} ({}: {})", id, state, origin),
+            None => format!("reference {} (unknown)", id),
+        }
+    }
+
+    /// Moves the origin of a dropped reference into the history, if history is retained.
+    ///
+    /// Once the history is full, the oldest origin in it is discarded. If IDs are recycled, the ID
+    /// of the reference becomes available again.
+    fn retire(&mut self, origin: Origin) {
+        if let Some(ref hook) = self.hook {
+            hook.on_drop(origin.id);
+        }
+
+        if let Some(ref mut free_ids) = self.free_ids {
+            free_ids.insert(origin.id);
+        }
+
+        if let Some(ref mut history) = self.history {
+            #[cfg(not(feature = "no_std"))]
+            let origin = Origin {
+                dropped_at: Some(Instant::now()),
+                ..origin
+            };
+            if history.len() >= self.history_limit {
+                history.pop_front();
+            }
+            if self.history_limit > 0 {
+                history.push_back(origin);
+            }
+        }
+    }
+}
+
+/// Panics with an internal consistency error about the reference with the given ID.
+///
+/// The lock on `map` is released before panicking, so that the family is not poisoned.
+fn inconsistent<G: Deref<Target = Map>>(map: G, context: &str, id: Uid) -> ! {
+    let description = map.describe(id);
+    drop(map);
+
+    panic!(
+        "Internal consistency error ({}): {} is not tracked as expected. This is a bug.",
+        context, description
+    )
+}
+
+/// Source of sites for references that would otherwise get `Site::Unknown`, see
+/// `set_default_site_provider`.
+///
+/// Holds a `fn() -> Site`, or is null if none is installed. An atomic instead of a lock, as it is
+/// read whenever a reference is created.
+static DEFAULT_SITE_PROVIDER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs `provider` to supply the site of every reference that would otherwise get
+/// `Site::Unknown`.
+///
+/// These are the references created through conversions like `From`, and those derived from a
+/// sampled value that are left out by sampling, see `Snarc::new_sampled`. Instead, they record the
+/// site returned by `provider`, e.g. one annotated with the name of the current async task.
+/// References recording the location of their caller or an explicit site are not affected, and
+/// labels of active scopes, see `scope::push`, still take precedence. Whenever `provider` returns
+/// `Site::Unknown`, so does the reference.
+///
+/// The provider is process-wide, shared by all threads, and replaces any previously installed one.
+/// It is called on every reference affected, possibly while the family of that reference is
+/// locked, so it should be cheap and not create or drop references itself. See
+/// `clear_default_site_provider` for removing it again.
+pub fn set_default_site_provider(provider: fn() -> Site) {
+    DEFAULT_SITE_PROVIDER.store(provider as *mut (), Ordering::Release);
+}
+
+/// Removes the provider installed by `set_default_site_provider`, if any.
+pub fn clear_default_site_provider() {
+    DEFAULT_SITE_PROVIDER.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Returns the site supplied by the default site provider, or `Site::Unknown` if there is none.
+fn provided() -> Site {
+    let provider = DEFAULT_SITE_PROVIDER.load(Ordering::Acquire);
+    if provider.is_null() {
+        return Site::Unknown;
+    }
+
+    // Safe, as it is only ever set from a `fn() -> Site`, see `set_default_site_provider`.
+    let provider = unsafe { mem::transmute::<*mut (), fn() -> Site>(provider) };
+    provider()
+}
+
+/// Returns the site for a reference created without an explicit one.
+///
+/// Inside a scope, see `scope::push`, its label is used instead of `site`. Otherwise, if `site` is
+/// `Site::Unknown`, the one returned by the default site provider, if any, is used.
+fn implicit(site: Site) -> Site {
+    #[cfg(not(feature = "no_std"))]
+    {
+        if let Some(label) = scope::current() {
+            return Site::Annotated(label);
+        }
+    }
+
+    match site {
+        Site::Unknown => provided(),
+        site => site,
+    }
+}
+
+/// Atomic counterpart of `Uid`.
+#[cfg(not(feature = "u64-ids"))]
+type AtomicUid = AtomicUsize;
+
+/// Atomic counterpart of `Uid`.
+#[cfg(feature = "u64-ids")]
+type AtomicUid = AtomicU64;
+
+/// Counter of dereferences, see `Snarc::access_count`.
+///
+/// 64 bits wide where the target supports it, so that it does not wrap around in practice.
+#[cfg(all(feature = "track-access", target_has_atomic = "64"))]
+type AccessCounter = AtomicU64;
+
+/// Counter of dereferences, see `Snarc::access_count`.
+///
+/// Pointer-sized, as the target lacks 64-bit atomics.
+#[cfg(all(feature = "track-access", not(target_has_atomic = "64")))]
+type AccessCounter = AtomicUsize;
+
+/// Tracking state shared by all references of a family.
+///
+/// Kept in an allocation separate from the value, so that it outlives the value for as long as any
+/// weak reference remains.
+#[derive(Debug)]
+pub(crate) struct Tracker {
+    /// Sibling metadata.
+    ///
+    /// For sharded families, see `Snarc::new_sharded`, only the first shard, holding the references
+    /// with IDs divisible by the number of shards.
+    map: RwLock<Map>,
+    /// The remaining shards of a sharded family, in order.
+    #[cfg(feature = "sharded")]
+    shards: Box<[RwLock<Map>]>,
+    /// ID of the next reference created, unless a recycled one is available.
+    ///
+    /// Kept outside of `map`, so that IDs can be allocated without locking.
+    next_id: AtomicUid,
+    /// Whether the IDs of dropped references are reused, see `Snarc::new_with_recycled_ids`.
+    recycles_ids: bool,
+    /// Number of times the value has been dereferenced through any reference.
+    #[cfg(feature = "track-access")]
+    accesses: AccessCounter,
+}
+
+impl Tracker {
+    /// Creates a new tracker for `map`.
+    ///
+    /// `id` must be the ID of the single `OriginKind::New` origin inside `map`.
+    fn new(map: Map, id: Uid) -> Arc<Tracker> {
+        Tracker::finish(Tracker {
+            recycles_ids: map.free_ids.is_some(),
+            map: RwLock::new(map),
+            #[cfg(feature = "sharded")]
+            shards: Box::new([]),
+            next_id: AtomicUid::new(id + 1),
+            #[cfg(feature = "track-access")]
+            accesses: AccessCounter::new(0),
+        })
+    }
+
+    /// Creates a new tracker for a family sharded across `map` and `shards`.
+    ///
+    /// Like for `Tracker::new`, `id` must be the ID of the single origin inside `map`, while all of
+    /// `shards` must be empty.
+    #[cfg(feature = "sharded")]
+    fn new_sharded(map: Map, id: Uid, shards: Vec<Map>) -> Arc<Tracker> {
+        Tracker::finish(Tracker {
+            recycles_ids: false,
+            map: RwLock::new(map),
+            shards: shards.into_iter().map(RwLock::new).collect(),
+            next_id: AtomicUid::new(id + 1),
+            #[cfg(feature = "track-access")]
+            accesses: AccessCounter::new(0),
+        })
+    }
+
+    /// Moves a new tracker into its allocation, registering it if the `registry` is enabled.
+    fn finish(tracker: Tracker) -> Arc<Tracker> {
+        let tracker = Arc::new(tracker);
+        #[cfg(feature = "registry")]
+        registry::register(&tracker);
+        tracker
+    }
+
+    /// Returns all shards of the family, starting with `map`.
+    fn shards(&self) -> impl Iterator<Item = &RwLock<Map>> {
+        let shards = Some(&self.map).into_iter();
+        #[cfg(feature = "sharded")]
+        let shards = shards.chain(self.shards.iter());
+        shards
+    }
+
+    /// Returns the shard holding the reference with the given ID.
+    #[cfg_attr(not(feature = "sharded"), allow(unused_variables))]
+    fn shard(&self, id: Uid) -> &RwLock<Map> {
+        #[cfg(feature = "sharded")]
+        {
+            // Not a no-op if `Uid` is a `u64`, see the `u64-ids` feature.
+            #[allow(clippy::unnecessary_cast)]
+            let index = (id % (self.shards.len() + 1) as Uid) as usize;
+            if index > 0 {
+                return &self.shards[index - 1];
+            }
+        }
+
+        &self.map
+    }
+
+    /// Locks all shards of the family for reading, for a consistent view of all of them.
+    fn read_all(&self) -> Vec<impl Deref<Target = Map> + '_> {
+        self.shards().map(RwLock::read).collect()
+    }
+
+    /// Allocates the ID for a new reference, locks the shard it is added to, and copies the origin
+    /// of its parent.
+    ///
+    /// `parent` is the ID of the strong reference or, if `weak` is set, the weak reference the new
+    /// one is derived from. Unless both are in the same shard, the parent's one is unlocked again
+    /// right after copying, so that no thread ever holds the locks of two shards for writing.
+    fn derive(
+        &self,
+        parent: Uid,
+        weak: bool,
+        context: &str,
+    ) -> (impl DerefMut<Target = Map> + '_, Origin, Uid) {
+        let copy = |map: &Map| {
+            let origins = if weak { &map.weaks } else { &map.strongs };
+            origins.get(&parent).cloned().unwrap_or_else(|| {
+                panic!(
+                    "Internal consistency error ({}). This should never happen.",
+                    context
+                )
+            })
+        };
+
+        // Families recycling IDs are never sharded, so the only map is locked either way.
+        if self.recycles_ids {
+            let mut map = self.map.write();
+            let origin = copy(&map);
+            let id = self.next_id(&mut map);
+            return (map, origin, id);
+        }
+
+        let id = self.fresh_ids(1).start;
+        let target = self.shard(id);
+        let source = self.shard(parent);
+        if ptr::eq(target, source) {
+            let map = target.write();
+            let origin = copy(&map);
+            (map, origin, id)
+        } else {
+            let origin = copy(&source.read());
+            (target.write(), origin, id)
+        }
+    }
+
+    /// Returns the origins of all strong and weak references in `maps`.
+    ///
+    /// With the `ordered` or `no_std` feature enabled, both are sorted by ID, even across shards.
+    fn merge<'a, I: IntoIterator<Item = &'a Map>>(maps: I) -> (Vec<Origin>, Vec<Origin>) {
+        let (mut strongs, mut weaks) = (Vec::new(), Vec::new());
+        let mut count = 0;
+        for map in maps {
+            let (more_strongs, more_weaks) = map.family();
+            strongs.extend(more_strongs);
+            weaks.extend(more_weaks);
+            count += 1;
+        }
+
+        if cfg!(any(feature = "no_std", feature = "ordered")) && count > 1 {
+            strongs.sort();
+            weaks.sort();
+        }
+        (strongs, weaks)
+    }
+
+    /// Returns the ID for a new reference added to `map`.
+    ///
+    /// If IDs are recycled, the lowest available one is reused. Otherwise, or if there is none, a
+    /// fresh one is allocated, see `Tracker::fresh_ids`.
+    fn next_id(&self, map: &mut Map) -> Uid {
+        map.free_ids
+            .as_mut()
+            .and_then(|free_ids| free_ids.pop_first())
+            .unwrap_or_else(|| self.fresh_ids(1).start)
+    }
+
+    /// Advances the `next_id` counter by `n` without locking, and returns the IDs skipped over.
+    ///
+    /// Panics if the counter would overflow, as the ID handed out after wrapping around could
+    /// collide with that of a live reference. With the `u64-ids` feature, this can not happen in
+    /// practice.
+    fn fresh_ids(&self, n: usize) -> Range<Uid> {
+        // Not a no-op if `Uid` is a `u64`, see the `u64-ids` feature.
+        #[allow(clippy::unnecessary_cast)]
+        let n = n as Uid;
+        let start = self
+            .next_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(n))
+            .expect(
+                "Snarc ID space exhausted. Enable the `u64-ids` feature or recycle IDs to avoid \
+                 this.",
+            );
+        start..start + n
+    }
+
+    /// Checks whether any strong reference, and thus the value, is still alive.
+    #[cfg(feature = "registry")]
+    pub(crate) fn is_alive(&self) -> bool {
+        self.read_all().iter().any(|map| !map.strongs.is_empty())
+    }
+
+    /// Returns the origins of all strong and weak references.
+    pub(crate) fn family(&self) -> (Vec<Origin>, Vec<Origin>) {
+        Tracker::merge(self.read_all().iter().map(|map| &**map))
+    }
+
+    /// Returns the origins of all strong and weak references, failing if any lock is poisoned.
+    ///
+    /// The origins can still be retrieved from the error using `PoisonError::into_inner`.
+    #[cfg(not(feature = "no_std"))]
+    #[allow(clippy::type_complexity)]
+    fn try_family(
+        &self,
+    ) -> Result<(Vec<Origin>, Vec<Origin>), PoisonError<(Vec<Origin>, Vec<Origin>)>> {
+        let mut poisoned = false;
+        let maps: Vec<_> = self
+            .shards()
+            .map(|shard| {
+                shard.checked_read().unwrap_or_else(|err| {
+                    poisoned = true;
+                    err.into_inner()
+                })
+            })
+            .collect();
+
+        let family = Tracker::merge(maps.iter().map(|map| &**map));
+        if poisoned {
+            Err(PoisonError::new(family))
+        } else {
+            Ok(family)
+        }
+    }
+}
+
+#[cfg(feature = "registry")]
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        registry::unregister(self);
+    }
+}
+
+/// A 'snitching' atomically reference counted pointer.
+///
+/// A `Snarc` wraps an actual `Arc` and assigns it a unique ID upon creation. Any offspring of
+/// created via `clone` or `downgrade` is tracked by being assigned a unique ID as well. If the
+/// annotating methods `new_at_line`, `clone_at_line`, etc. are used, the `Snarc` will also know
+/// its origin.
+///
+/// When debug formatted, only the ID and the value are shown, along with the origin chain if the
+/// alternate flag (`{:#?}`) is used.
+pub struct Snarc<T: ?Sized> {
+    /// Wrapped [std::sync] arc reference.
+    inner: Arc<T>,
+    /// Tracking state of the family.
+    tracker: Arc<Tracker>,
+    /// Unique ID for this instance.
+    id: Uid,
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Snarc<U>> for Snarc<T> {}
+
+/// The non-owned version of a `Snarc`.
+///
+/// When debug formatted, the ID and whether the value is still alive are shown, along with the
+/// value if it is. When displayed, the origin chain is shown.
+pub struct Weak<T: ?Sized> {
+    /// Unique ID for this instance.
+    id: Option<Uid>,
+    /// Tracking state of the family, present if and only if `id` is.
+    tracker: Option<Arc<Tracker>>,
+    /// Wrapped non-owned [std::sync] arc reference.
+    inner: ArcWeak<T>,
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Weak<U>> for Weak<T> {}
+
+impl<T> Snarc<T> {
+    /// Internal instantiation function.
+    ///
+    /// Directly accepts a `Site` instance, creates the correct `Origin` with `OriginKind::New`.
+    fn new_at_site(data: T, site: Site) -> Snarc<T> {
+        let (map, id) = Map::seeded(site);
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Internal instantiation function for preconfigured maps.
+    ///
+    /// `id` must be the ID of the single `OriginKind::New` origin inside `map`.
+    fn with_map(data: T, map: Map, id: Uid) -> Snarc<T> {
+        Snarc {
+            inner: Arc::new(data),
+            tracker: Tracker::new(map, id),
+            id,
+        }
+    }
+
+    /// Returns a new `Snarc` with the provided location as the origin.
+    ///
+    /// Meant for callers that already have a `Location` at hand, e.g. from `Location::caller` in
+    /// their own `#[track_caller]` function. Unlike `new_at_line`, the column is recorded as well.
+    pub fn new_at_location(data: T, location: &Location<'static>) -> Snarc<T> {
+        Snarc::new_at_site(data, Site::from(location))
+    }
+
+    /// Returns a new `Snarc` with the provided file name and line as the origin.
+    pub fn new_at_line(data: T, file: &'static str, line: u32) -> Snarc<T> {
+        Snarc::new_at_site(
+            data,
+            Site::SourceFile {
+                file,
+                line,
+                column: None,
+            },
+        )
+    }
+
+    /// Returns a new `Snarc` with the provided module path as the origin.
+    ///
+    /// Meant to be called with `module_path!()`, see also `snarc_in_module!`.
+    pub fn new_in_module(data: T, module: &'static str) -> Snarc<T> {
+        Snarc::new_at_site(data, Site::Module(module))
+    }
+
+    /// Returns a new `Snarc` with the provided label as the origin.
+    pub fn new_annotated<S: Into<String>>(data: T, label: S) -> Snarc<T> {
+        Snarc::new_at_site(data, Site::Annotated(label.into()))
+    }
+
+    /// Creates new `Snarc`, with the location of the caller as the origin.
+    #[track_caller]
+    pub fn new(data: T) -> Snarc<T> {
+        Snarc::new_at_site(data, implicit(Site::caller()))
+    }
+
+    /// Creates new `Snarc`, with room for tracking `capacity` strong and weak references each
+    /// before the family has to grow.
+    ///
+    /// By default, only a handful of references are accounted for. With the `ordered` or `no_std`
+    /// feature enabled, families are kept in a `BTreeMap` and the capacity is ignored.
+    #[track_caller]
+    pub fn with_capacity(data: T, capacity: usize) -> Snarc<T> {
+        let mut map = Map::new(capacity);
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates a new pinned `Snarc`, with the location of the caller as the origin.
+    ///
+    /// If `T` does not implement `Unpin`, the value can not be moved out of the `Snarc` again. See
+    /// `std::sync::Arc::pin` for details.
+    #[track_caller]
+    pub fn pin(data: T) -> Pin<Snarc<T>> {
+        // Safe, as `Snarc` never moves its value, just like `Arc`.
+        unsafe { Pin::new_unchecked(Snarc::new_at_site(data, implicit(Site::caller()))) }
+    }
+
+    /// Creates new `Snarc` that retains the origins of dropped references.
+    ///
+    /// Instead of being discarded, the origin of every reference dropped is kept, see
+    /// `Snarc::history`. As the history is never cleared, memory usage grows with every reference
+    /// created, see `Snarc::new_with_bounded_history` for an alternative.
+    #[track_caller]
+    pub fn new_with_history(data: T) -> Snarc<T> {
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        map.history = Some(VecDeque::new());
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates new `Snarc` that retains the origins of the `limit` most recently dropped references.
+    ///
+    /// Like `Snarc::new_with_history`, but once `limit` origins are retained, the oldest one is
+    /// discarded for every reference dropped, keeping memory usage bounded.
+    #[track_caller]
+    pub fn new_with_bounded_history(data: T, limit: usize) -> Snarc<T> {
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        map.history = Some(VecDeque::with_capacity(limit.min(DEFAULT_CAPACITY)));
+        map.history_limit = limit;
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates new `Snarc` that only records the site of every `rate`-th reference derived from it.
+    ///
+    /// All clones, downgrades and upgrades are still tracked, but only the first one and every
+    /// `rate`-th after it record their site, the others get `Site::Unknown`, unless a default site
+    /// provider supplies one, see `set_default_site_provider`. This bounds the overhead of tracking
+    /// on hot paths while retaining a representative sample of sites. Note that sites, including
+    /// backtraces, are still captured by the callers before being discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    #[track_caller]
+    pub fn new_sampled(data: T, rate: usize) -> Snarc<T> {
+        assert!(rate > 0, "Sampling rate must not be zero.");
+
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        map.sample_rate = rate;
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates new `Snarc` that reuses the IDs of dropped references.
+    ///
+    /// Every new reference is assigned the lowest ID available, keeping IDs small for values with
+    /// many short-lived references. The tradeoff is that IDs are only unique among the live
+    /// references: a dropped reference may share its ID with a later one, e.g. in its history (see
+    /// `Snarc::history`), or with an ancestor in the origin chain of a live reference. Lineage is
+    /// not affected, as origin chains hold their ancestors by value, but views arranging references
+    /// by ID, like `Snarc::family_tree` or `Dot`, may conflate the two.
+    #[track_caller]
+    pub fn new_with_recycled_ids(data: T) -> Snarc<T> {
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        map.free_ids = Some(BTreeSet::new());
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates new `Snarc` that reports the creation and drop of every reference to `hook`.
+    ///
+    /// See `RefHook` for details.
+    #[track_caller]
+    pub fn new_with_hook<H: RefHook + 'static>(data: T, hook: H) -> Snarc<T> {
+        let mut map = Map::new(DEFAULT_CAPACITY);
+        map.hook = Some(Box::new(hook));
+        let id = map.seed(implicit(Site::caller()));
+        Snarc::with_map(data, map, id)
+    }
+
+    /// Creates new `Snarc` whose references are tracked in `shards` separately locked partitions.
+    ///
+    /// Every clone, downgrade, upgrade and drop locks the family, which serializes them across
+    /// threads for values with many references in flight. Here, the reference with the ID `id`
+    /// lives in shard `id % shards` instead, and new references are assigned to the shards in
+    /// turn, so that concurrent operations mostly lock different ones. Functions inspecting the
+    /// whole family, like `Snarc::family` or `Dump`, lock all shards at once.
+    ///
+    /// Apart from that, the family is tracked like one created by `Snarc::new`, except that
+    /// `Snarc::peak_strong_count` and `Snarc::peak_weak_count` add up the peaks of the individual
+    /// shards, which makes them an upper bound. See `Snarc::contention_count` for measuring whether
+    /// sharding pays off.
+    ///
+    /// Requires the `sharded` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    #[cfg(feature = "sharded")]
+    #[track_caller]
+    pub fn new_sharded(data: T, shards: usize) -> Snarc<T> {
+        assert!(shards > 0, "Number of shards must not be zero.");
+
+        let (map, id) = Map::seeded(implicit(Site::caller()));
+        let others = (1..shards).map(|_| Map::new(DEFAULT_CAPACITY)).collect();
+
+        Snarc {
+            inner: Arc::new(data),
+            tracker: Tracker::new_sharded(map, id, others),
+            id,
+        }
+    }
+
+    /// Creates a new `Snarc`, with a full stack trace of the caller as the origin.
+    ///
+    /// See `CapturedBacktrace::capture` for details.
+    #[cfg(feature = "backtrace")]
+    pub fn new_with_backtrace(data: T) -> Snarc<T> {
+        Snarc::new_at_site(data, Site::Backtrace(CapturedBacktrace::capture()))
+    }
+
+    /// Creates a new `Snarc` to a value that holds a `Weak` to itself.
+    ///
+    /// `data_fn` is handed a `Weak` downgraded from the reference under construction, which can be
+    /// cloned into the value. Upgrading it fails until `new_cyclic` has returned. The location of
+    /// the caller is recorded as the site of both the new `Snarc` and the `Weak` handed out.
+    ///
+    /// See `std::sync::Arc::new_cyclic` for details.
+    #[track_caller]
+    pub fn new_cyclic<F: FnOnce(&Weak<T>) -> T>(data_fn: F) -> Snarc<T> {
+        let site = implicit(Site::caller());
+        let (map, id) = Map::seeded(site.clone());
+        let tracker = Tracker::new(map, id);
+
+        let inner = Arc::new_cyclic(|inner| {
+            let weak_id = {
+                // A new family never recycles IDs, so none has to be popped from the map.
+                let weak_id = tracker.fresh_ids(1).start;
+                let mut map = tracker.map.write();
+                let prev_origin = map
+                    .strongs
+                    .get(&id)
+                    .expect("Internal consistency error (new_cyclic). This should never happen.")
+                    .clone();
+                map.insert_weak(Origin::new(
+                    weak_id,
+                    site,
+                    OriginKind::Downgraded(Arc::new(prev_origin)),
+                ));
+                weak_id
+            };
+
+            data_fn(&Weak {
+                inner: inner.clone(),
+                tracker: Some(tracker.clone()),
+                id: Some(weak_id),
+            })
+        });
+
+        Snarc { inner, tracker, id }
+    }
+
+    /// Returns the contained value if the `Snarc` has exactly one strong reference.
+    ///
+    /// Otherwise, an `Err` is returned with the same `Snarc` that was passed in, its tracking
+    /// information left untouched.
+    ///
+    /// See `std::sync::Arc::try_unwrap` for details.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        let (inner, tracker, id) = Snarc::into_parts(this);
+
+        match Arc::try_unwrap(inner) {
+            // We've dissolved our Snarc, as we are the last strong reference. Any remaining weak
+            // references still share the tracker, so our entry must be removed.
+            Ok(data) => {
+                let mut map = tracker.shard(id).write();
+                if map.remove_strong(id).is_none() {
+                    inconsistent(map, "try_unwrap", id);
+                }
+                Ok(data)
+            }
+            Err(inner) => Err(Snarc { inner, tracker, id }),
+        }
+    }
+
+    /// Returns the contained value once the `Snarc` is the only strong reference left, waiting at
+    /// most `timeout` for the others to be dropped.
+    ///
+    /// Polls the strong count, sleeping for increasingly longer in between, until it drops to one
+    /// or the timeout expires, then attempts `Snarc::try_unwrap`. A timeout too long to represent,
+    /// like `Duration::MAX`, waits indefinitely. Weak references do not prevent unwrapping, so they
+    /// are not waited for.
+    #[cfg(not(feature = "no_std"))]
+    pub fn try_unwrap_timeout(this: Self, timeout: Duration) -> Result<T, Self> {
+        wait_for(timeout, || Snarc::strong_count(&this) == 1);
+        Snarc::try_unwrap(this)
+    }
+
+    /// Returns the contained value if the `Snarc` is the last strong reference.
+    ///
+    /// Otherwise, the `Snarc` is dropped like any other and `None` is returned.
+    ///
+    /// See `std::sync::Arc::into_inner` for details.
+    pub fn into_inner(this: Self) -> Option<T> {
+        let (inner, tracker, id) = Snarc::into_parts(this);
+
+        // This reference ceases to exist regardless of whether it was the last one.
+        let mut map = tracker.shard(id).write();
+        if map.remove_strong(id).is_none() {
+            inconsistent(map, "into_inner", id);
+        }
+        drop(map);
+
+        Arc::into_inner(inner)
+    }
+}
+
+impl<T: ?Sized> Snarc<T> {
+    /// Dissolves a `Snarc` into its wrapped `Arc`, tracker and ID without running its `Drop` impl.
+    ///
+    /// The tracking entry of the reference is left in the map; the caller is responsible for
+    /// either removing it or putting the parts back together.
+    fn into_parts(this: Self) -> (Arc<T>, Arc<Tracker>, Uid) {
+        let this = ManuallyDrop::new(this);
+        // Safe, since `this` is never used or dropped again.
+        let (inner, tracker) = unsafe { (ptr::read(&this.inner), ptr::read(&this.tracker)) };
+        (inner, tracker, this.id)
+    }
+
+    /// Internal instantiation function for existing, possibly unsized `Arc`s.
+    ///
+    /// Starts a new family for `inner`, with a single `OriginKind::New` origin at `site`.
+    fn from_arc_at_site(inner: Arc<T>, site: Site) -> Snarc<T> {
+        let (map, id) = Map::seeded(site);
+
+        Snarc {
+            inner,
+            tracker: Tracker::new(map, id),
+            id,
+        }
+    }
+
+    /// Internal cloning function.
+    ///
+    /// Directly accepts a `Site` instance, creates the correct `Origin` with
+    /// `OriginKind::Cloned`.
+    fn clone_at_site(&self, site: Site) -> Snarc<T> {
+        let (mut map, parent_origin, new_id) = self.tracker.derive(self.id, false, "clone");
+        let site = map.sample(site);
+        let new_origin = Origin::new(new_id, site, OriginKind::Cloned(Arc::new(parent_origin)));
+        map.insert_strong(new_origin);
+
+        Snarc {
+            inner: self.inner.clone(),
+            tracker: self.tracker.clone(),
+            id: new_id,
+        }
+    }
+
+    /// Internal downgrade function.
+    ///
+    /// Directly accepts a `Site` instance, creates the correct `Origin` with
+    /// `OriginKind::Downgraded`.
+    fn downgrade_at_site(this: &Self, site: Site) -> Weak<T> {
+        // No need to `::remove` here because the strong ref will be dropped.
+        let (mut map, prev_origin, new_id) = this.tracker.derive(this.id, false, "downgrade");
+        let site = map.sample(site);
+        let new_origin = Origin::new(new_id, site, OriginKind::Downgraded(Arc::new(prev_origin)));
+        map.insert_weak(new_origin);
+
+        Weak {
+            inner: Arc::downgrade(&this.inner),
+            tracker: Some(this.tracker.clone()),
+            id: Some(new_id),
+        }
+    }
+
+    /// Clones `Snarc` with the provided file name and line as the origin.
+    pub fn clone_at_line(&self, file: &'static str, line: u32) -> Snarc<T> {
+        self.clone_at_site(Site::SourceFile {
+            file,
+            line,
+            column: None,
+        })
+    }
+
+    /// Clones `Snarc` with the provided location as the origin, see `Snarc::new_at_location`.
+    pub fn clone_at_location(&self, location: &Location<'static>) -> Snarc<T> {
+        self.clone_at_site(Site::from(location))
+    }
+
+    /// Clones `Snarc` with the provided label as the origin.
+    pub fn clone_annotated<S: Into<String>>(&self, label: S) -> Snarc<T> {
+        self.clone_at_site(Site::Annotated(label.into()))
+    }
+
+    /// Creates `k` clones of `this` at once, with the provided file name and line as their origin.
+    ///
+    /// Each clone gets its own ID, but all of them share the same site and parent. Unlike calling
+    /// `clone_at_line` in a loop, the family is only locked once for all clones.
+    pub fn clone_n(this: &Snarc<T>, k: usize, file: &'static str, line: u32) -> Vec<Snarc<T>> {
+        let mut clones = Vec::with_capacity(k);
+        Snarc::clone_into(this, &mut clones, k, file, line);
+        clones
+    }
+
+    /// Appends `n` clones of `this` to `dest` at once, with the provided file name and line as
+    /// their origin.
+    ///
+    /// Like `Snarc::clone_n`, but reuses a vector owned by the caller, e.g. one that is seeded
+    /// repeatedly, instead of allocating a new one.
+    pub fn clone_into(
+        this: &Snarc<T>,
+        dest: &mut Vec<Snarc<T>>,
+        n: usize,
+        file: &'static str,
+        line: u32,
+    ) {
+        let tracker = &this.tracker;
+        let site = Site::SourceFile {
+            file,
+            line,
+            column: None,
+        };
+        let snarc = |id| Snarc {
+            inner: this.inner.clone(),
+            tracker: tracker.clone(),
+            id,
+        };
+
+        if tracker.recycles_ids {
+            let mut map = tracker.map.write();
+            let parent_origin = Arc::new(
+                map.strongs
+                    .get(&this.id)
+                    .cloned()
+                    .expect("Internal consistency error (clone_into). This should never happen."),
+            );
+
+            dest.extend((0..n).map(|_| {
+                let new_id = tracker.next_id(&mut map);
+                let site = map.sample(site.clone());
+                map.insert_strong(Origin::new(
+                    new_id,
+                    site,
+                    OriginKind::Cloned(parent_origin.clone()),
+                ));
+                snarc(new_id)
+            }));
+            return;
+        }
+
+        // Allocated at once, so that the clones get consecutive IDs, and added shard by shard, so
+        // that each shard is locked only once.
+        let ids = tracker.fresh_ids(n);
+        let parent_origin = Arc::new(
+            tracker
+                .shard(this.id)
+                .read()
+                .strongs
+                .get(&this.id)
+                .cloned()
+                .expect("Internal consistency error (clone_into). This should never happen."),
+        );
+        for shard in tracker.shards() {
+            let mut map = shard.write();
+            for new_id in ids.clone().filter(|&id| ptr::eq(tracker.shard(id), shard)) {
+                let site = map.sample(site.clone());
+                map.insert_strong(Origin::new(
+                    new_id,
+                    site,
+                    OriginKind::Cloned(parent_origin.clone()),
+                ));
+            }
+        }
+        dest.extend(ids.map(snarc));
+    }
+
+    /// Creates a new `Weak` pointer to this value with the provided file name and line as the
+    /// origin.
+    pub fn downgrade_at_line(this: &Self, file: &'static str, line: u32) -> Weak<T> {
+        Snarc::downgrade_at_site(
+            this,
+            Site::SourceFile {
+                file,
+                line,
+                column: None,
+            },
+        )
+    }
+
+    /// Creates a new `Weak` pointer to this value with the provided location as the origin, see
+    /// `Snarc::new_at_location`.
+    pub fn downgrade_at_location(this: &Self, location: &Location<'static>) -> Weak<T> {
+        Snarc::downgrade_at_site(this, Site::from(location))
+    }
+
+    /// Creates a new `Weak` pointer to this value, with the location of the caller as the origin.
+    #[track_caller]
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        Snarc::downgrade_at_site(this, implicit(Site::caller()))
+    }
+
+    /// Creates a new `Weak` pointer to this value with the provided label as the origin.
+    pub fn downgrade_annotated<S: Into<String>>(this: &Self, label: S) -> Weak<T> {
+        Snarc::downgrade_at_site(this, Site::Annotated(label.into()))
+    }
+
+    /// Creates a new `Weak` pointer to this value with the provided file name and line as the
+    /// origin.
+    ///
+    /// An alias of `Snarc::downgrade_at_line`, for code that reads better in terms of creating a
+    /// weak reference than of downgrading a strong one.
+    pub fn new_weak(this: &Self, file: &'static str, line: u32) -> Weak<T> {
+        Snarc::downgrade_at_line(this, file, line)
+    }
+
+    /// Creates a new `Weak` pointer to this value with the provided file name and line as the
+    /// origin.
+    ///
+    /// Equivalent to `Snarc::new_weak` and `Snarc::downgrade_at_line`; the weak reference is a
+    /// sibling of all other references in the family, with this one as its parent.
+    pub fn clone_weak(this: &Self, file: &'static str, line: u32) -> Weak<T> {
+        Snarc::downgrade_at_line(this, file, line)
+    }
+
+    /// Gets the number of `Weak` pointers to this value.
+    ///
+    /// See `std::sync::Arc::weak_count` for details.
+    pub fn weak_count(this: &Snarc<T>) -> usize {
+        Arc::weak_count(&this.inner)
+    }
+
+    /// Gets the number of `Snarc` pointers to this value.
+    ///
+    /// See `std::sync::Arc::strong_count` for details.
+    pub fn strong_count(this: &Snarc<T>) -> usize {
+        Arc::strong_count(&this.inner)
+    }
+
+    /// Returns the number of times the value has been dereferenced, through any of its references.
+    ///
+    /// Only `Deref::deref` is counted, including implicit dereferences like method calls on the
+    /// value; `AsRef` and `Borrow` are not. On targets without 64-bit atomics, the counter wraps
+    /// around after `usize::MAX` accesses.
+    ///
+    /// Requires the `track-access` feature.
+    #[cfg(feature = "track-access")]
+    pub fn access_count(this: &Snarc<T>) -> u64 {
+        // Not a no-op on targets without 64-bit atomics, see `AccessCounter`.
+        #[allow(clippy::unnecessary_cast)]
+        let count = this.tracker.accesses.load(Ordering::Relaxed) as u64;
+        count
+    }
+
+    /// Returns how often locking the family of this value had to wait for another thread so far.
+    ///
+    /// Every clone, downgrade, upgrade and drop locks the family, as do most functions inspecting
+    /// it. A count that grows quickly relative to the number of references created suggests that
+    /// this lock is a bottleneck.
+    ///
+    /// Requires the `contention` feature.
+    #[cfg(feature = "contention")]
+    pub fn contention_count(this: &Snarc<T>) -> usize {
+        this.tracker.shards().map(RwLock::contended).sum()
+    }
+
+    /// Returns the highest number of strong references to this value alive at the same time so far.
+    ///
+    /// For sharded families, see `Snarc::new_sharded`, this is the sum of the peaks of the
+    /// individual shards. As these may have been reached at different times, it is only an upper
+    /// bound of the actual peak.
+    pub fn peak_strong_count(this: &Snarc<T>) -> usize {
+        this.tracker
+            .shards()
+            .map(|shard| shard.read().peak_strongs)
+            .sum()
+    }
+
+    /// Returns the highest number of tracked `Weak` pointers to this value alive at the same time so
+    /// far.
+    ///
+    /// Weak references are counted even after the value has been dropped. For sharded families,
+    /// this is an upper bound, like `Snarc::peak_strong_count`.
+    pub fn peak_weak_count(this: &Snarc<T>) -> usize {
+        this.tracker
+            .shards()
+            .map(|shard| shard.read().peak_weaks)
+            .sum()
+    }
+
+    /// Returns true if the two Arcs point to the same value (not just values that compare as equal).
+    ///
+    /// See `std::sync::Arc::ptr_eq` for details.
+    pub fn ptr_eq(this: &Snarc<T>, other: &Snarc<T>) -> bool {
+        Arc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    /// Returns true if `weak` points to the same allocation as this reference.
+    ///
+    /// Unlike comparing against the result of `Weak::upgrade`, this creates no reference and
+    /// therefore leaves the family untouched. A `Weak` created by `Weak::new` never matches.
+    pub fn ptr_eq_weak(this: &Snarc<T>, weak: &Weak<T>) -> bool {
+        Arc::as_ptr(&this.inner) as *const u8 == weak.inner.as_ptr() as *const u8
+    }
+
+    /// Returns whether `descendant` was derived from `ancestor`, directly or transitively.
+    ///
+    /// The origin chain of `descendant` is searched for the ID of `ancestor`, so derivations
+    /// through weak references, like an upgrade of a downgrade, count as well. A reference is not
+    /// its own ancestor. Returns `None` if the two are not in the same family, e.g. if one of them
+    /// was created from a plain `Arc` to the same value.
+    ///
+    /// For values created using `Snarc::new_with_recycled_ids`, the ID of a dropped ancestor may
+    /// have been reused by `ancestor`, in which case the result is `true` regardless.
+    pub fn is_ancestor_of(ancestor: &Snarc<T>, descendant: &Snarc<T>) -> Option<bool> {
+        if !Arc::ptr_eq(&ancestor.tracker, &descendant.tracker) {
+            return None;
+        }
+
+        let origin = Snarc::origin(descendant);
+        let found = origin
+            .ancestors()
+            .skip(1)
+            .any(|link| link.id == ancestor.id);
+        Some(found)
+    }
+
+    /// Converts the `Snarc` into a plain `Arc` to the same value, ending its tracking.
+    ///
+    /// The value is neither moved nor cloned. Only this reference leaves its family, any other
+    /// `Snarc`s and `Weak`s to the value remain tracked, while the returned `Arc` and any clones of
+    /// it still count towards `Snarc::strong_count`.
+    ///
+    /// If this was the last tracked strong reference, the family is no longer counted by the
+    /// registry, see `snarc::registry`, even though the value stays alive.
+    pub fn into_arc(this: Snarc<T>) -> Arc<T> {
+        let (inner, tracker, id) = Snarc::into_parts(this);
+
+        let mut map = tracker.shard(id).write();
+        if map.remove_strong(id).is_none() {
+            inconsistent(map, "into_arc", id);
+        }
+
+        inner
+    }
+
+    /// Converts the `Snarc` into one of an unsized type, such as a slice or trait object.
+    ///
+    /// `f` must return the `Arc` passed to it, coerced to the target type, e.g. `|arc| arc as
+    /// Arc<dyn Trait>`. This allows the coercion on stable Rust, see the crate documentation. The
+    /// reference keeps its ID and origin.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` returns an `Arc` pointing to a different allocation.
+    pub fn unsize_with<U, F>(this: Snarc<T>, f: F) -> Snarc<U>
+    where
+        U: ?Sized,
+        F: FnOnce(Arc<T>) -> Arc<U>,
+    {
+        let (inner, tracker, id) = Snarc::into_parts(this);
+        let address = Arc::as_ptr(&inner) as *const u8;
+        let inner = f(inner);
+
+        if Arc::as_ptr(&inner) as *const u8 != address {
+            // Our reference is gone, keep the family consistent before bailing out.
+            tracker.shard(id).write().remove_strong(id);
+            panic!("Unsizing conversion returned a different allocation.");
+        }
+
+        Snarc { inner, tracker, id }
+    }
+
+    /// Returns a reference to a part of the value, e.g. a field, selected by `f`.
+    ///
+    /// The returned `SnarcRef` keeps `this`, and with it the whole value, alive.
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(this: Snarc<T>, f: F) -> SnarcRef<T, U> {
+        SnarcRef::new(this, f)
+    }
+
+    /// Returns a raw pointer to the value.
+    ///
+    /// The pointer is valid as long as there are strong references and is the same for all of
+    /// them. See `std::sync::Arc::as_ptr` for details.
+    pub fn as_ptr(this: &Snarc<T>) -> *const T {
+        Arc::as_ptr(&this.inner)
+    }
+
+    /// Returns true if this is the only reference to the value, strong or weak.
+    ///
+    /// This is exactly the condition under which `Snarc::get_mut` succeeds.
+    pub fn is_unique(this: &Snarc<T>) -> bool {
+        Snarc::strong_count(this) == 1 && Snarc::weak_count(this) == 0
+    }
+
+    /// Returns a mutable reference to the inner value, if there are no other Arc or Weak pointers
+    /// to the same value.
+    ///
+    /// See `std::sync::Arc::make_mut` for details.
+    pub fn get_mut(this: &mut Snarc<T>) -> Option<&mut T> {
+        Arc::get_mut(&mut this.inner)
+    }
+
+    /// Like `get_mut`, but explains a failure.
+    ///
+    /// If the value is shared, the returned `SharedError` holds the reference counts and a `Dump`
+    /// of the family, showing where the other references were created.
+    pub fn try_get_mut(this: &mut Snarc<T>) -> Result<&mut T, SharedError> {
+        if Arc::get_mut(&mut this.inner).is_none() {
+            return Err(SharedError::new(this));
+        }
+
+        Ok(Arc::get_mut(&mut this.inner)
+            .expect("Value became shared while borrowed. This is a bug."))
+    }
+
+    /// Returns a mutable reference to the inner value, if `this` is unique.
+    ///
+    /// The mutable counterpart of `Borrow::borrow`, same as `get_mut`. There is no `BorrowMut`
+    /// implementation, as its `borrow_mut` can not fail.
+    pub fn borrow_mut(this: &mut Snarc<T>) -> Option<&mut T> {
+        Snarc::get_mut(this)
+    }
+
+    /// Returns the unique ID of this reference.
+    ///
+    /// The ID matches the one shown in angle brackets for this reference in `Dump` output.
+    pub fn id(this: &Snarc<T>) -> Uid {
+        this.id
+    }
+
+    /// Labels this reference, replacing the site of its origin with `Site::Annotated`.
+    ///
+    /// Only the origin of this reference is changed; references that were already created from it
+    /// keep the previous site in their origin chains.
+    pub fn annotate<S: Into<String>>(this: &Snarc<T>, label: S) {
+        this.tracker
+            .shard(this.id)
+            .write()
+            .strongs
+            .get_mut(&this.id)
+            .expect("Internal consistency error (annotate). This is a bug.")
+            .site = Site::Annotated(label.into());
+    }
+
+    /// Removes the label of this reference, replacing the site of its origin with `Site::Unknown`.
+    ///
+    /// The site captured before `Snarc::annotate` is not retained, so it can not be restored. Sites
+    /// other than `Site::Annotated` are left unchanged, as are the origin chains of references
+    /// already created from this one.
+    pub fn clear_annotation(this: &Snarc<T>) {
+        let mut map = this.tracker.shard(this.id).write();
+        let origin = map
+            .strongs
+            .get_mut(&this.id)
+            .expect("Internal consistency error (clear_annotation). This is a bug.");
+
+        if let Site::Annotated(_) = origin.site {
+            origin.site = Site::Unknown;
+        }
+    }
+
+    /// Returns the origin chain of this reference.
+    ///
+    /// The resulting `Origin` can be printed using `fmt::Display`, see the `tracing` docs for
+    /// details.
+    ///
+    /// Should a thread have panicked while holding the lock on the family, the origin is returned
+    /// regardless, see `Snarc::try_origin` to detect this.
+    pub fn origin(this: &Snarc<T>) -> Origin {
+        this.tracker.shard(this.id).read().origin(this.id)
+    }
+
+    /// Returns the origin chain of this reference, failing if the family's lock is poisoned.
+    ///
+    /// The lock is poisoned if a thread panicked while holding it, e.g. inside a `RefHook`. The
+    /// origin can still be retrieved from the error using `PoisonError::into_inner`.
+    #[cfg(not(feature = "no_std"))]
+    pub fn try_origin(this: &Snarc<T>) -> Result<Origin, PoisonError<Origin>> {
+        this.tracker
+            .shard(this.id)
+            .checked_read()
+            .map(|map| map.origin(this.id))
+            .map_err(|err| PoisonError::new(err.into_inner().origin(this.id)))
+    }
+
+    /// Returns the origin of the reference and all of its siblings.
+    ///
+    /// Returns a tuple of (strong origins, weak origins), including all live references. Like
+    /// `Snarc::origin`, a poisoned lock is ignored.
+    ///
+    /// With the `ordered` or `no_std` feature enabled, both are sorted by ID; otherwise their order
+    /// is unspecified.
+    pub fn family(this: &Snarc<T>) -> (Vec<Origin>, Vec<Origin>) {
+        this.tracker.family()
+    }
+
+    /// Calls `f` with the origin of every live strong reference of the family, without cloning
+    /// them like `Snarc::family` does.
+    ///
+    /// The origins are visited in no particular order. As the family is locked meanwhile, `f` must
+    /// not create or drop references of it, or inspect it through other functions that lock it.
+    pub fn for_each_strong_origin<F: FnMut(&Origin)>(this: &Snarc<T>, f: F) {
+        this.tracker
+            .read_all()
+            .iter()
+            .flat_map(|map| map.strongs.values())
+            .for_each(f);
+    }
+
+    /// Calls `f` with the origin of every live weak reference of the family, see
+    /// `Snarc::for_each_strong_origin`.
+    pub fn for_each_weak_origin<F: FnMut(&Origin)>(this: &Snarc<T>, f: F) {
+        this.tracker
+            .read_all()
+            .iter()
+            .flat_map(|map| map.weaks.values())
+            .for_each(f);
+    }
+
+    /// Captures the live references of the family, to be compared with a later snapshot.
+    ///
+    /// See `FamilySnapshot::diff`.
+    pub fn snapshot(this: &Snarc<T>) -> FamilySnapshot {
+        FamilySnapshot::new(Snarc::family(this))
+    }
+
+    /// Returns the family of the reference arranged by descent.
+    ///
+    /// Unlike `Snarc::family`, this includes the dropped references that live ones descend from.
+    pub fn family_tree(this: &Snarc<T>) -> FamilyTree {
+        FamilyTree::new(Snarc::family(this))
+    }
+
+    /// Returns the origins of all live strong references, those closest to the root first.
+    ///
+    /// Origins are sorted by `Origin::depth`, ties are broken by ID. References created early in
+    /// the chain are often the long-lived holders of a value, so they are listed before the
+    /// transient ones derived from them.
+    pub fn origins_by_depth(this: &Snarc<T>) -> Vec<Origin> {
+        let (mut strongs, _) = Snarc::family(this);
+        strongs.sort_by_cached_key(|origin| (origin.depth(), origin.id));
+        strongs
+    }
+
+    /// Returns the origins of all live references, strong ones first, created in a source file
+    /// whose name contains `file`.
+    ///
+    /// Only the own site of each reference is considered, it has to be a `Site::SourceFile`. Unlike
+    /// `DumpFilter::File`, references merely descending from one created in the file are left out.
+    pub fn origins_from_file(this: &Snarc<T>, file: &str) -> Vec<Origin> {
+        let (strongs, weaks) = Snarc::family(this);
+        strongs
+            .into_iter()
+            .chain(weaks)
+            .filter(|origin| match origin.site {
+                Site::SourceFile { file: name, .. } => name.contains(file),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Returns the origin of the reference and all of its siblings, failing if the family's lock is
+    /// poisoned.
+    ///
+    /// See `Snarc::try_origin` for details.
+    #[cfg(not(feature = "no_std"))]
+    #[allow(clippy::type_complexity)]
+    pub fn try_family(
+        this: &Snarc<T>,
+    ) -> Result<(Vec<Origin>, Vec<Origin>), PoisonError<(Vec<Origin>, Vec<Origin>)>> {
+        this.tracker.try_family()
+    }
+
+    /// Returns the origins of all dropped references of the family, in the order they were dropped.
+    ///
+    /// History is only retained for values created using `Snarc::new_with_history`, for all others
+    /// the result is always empty. Each returned origin has its `dropped_at` time set.
+    ///
+    /// For values created using `Snarc::new_with_bounded_history`, only the most recently dropped
+    /// references are included.
+    pub fn history(this: &Snarc<T>) -> Vec<Origin> {
+        this.tracker
+            .map
+            .read()
+            .history
+            .iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Counts the live references of the family by the kind of their own origin.
+    ///
+    /// Both strong and weak references are counted. Only the last link of each origin chain is
+    /// taken into account, e.g. a clone of an upgraded reference counts as cloned.
+    pub fn kind_counts(this: &Snarc<T>) -> KindCounts {
+        let mut counts = KindCounts::default();
+        for map in this.tracker.read_all() {
+            for origin in map.strongs.values().chain(map.weaks.values()) {
+                counts.add(&origin.kind);
+            }
+        }
+        counts
+    }
+
+    /// Counts the live references of the family by site.
+    ///
+    /// Both strong and weak references are counted, grouped by their own site (not that of any of
+    /// their ancestors).
+    #[cfg(not(feature = "no_std"))]
+    pub fn site_histogram(this: &Snarc<T>) -> HashMap<Site, usize> {
+        let (strongs, weaks) = Snarc::family(this);
+
+        let mut histogram = HashMap::new();
+        for origin in strongs.into_iter().chain(weaks) {
+            *histogram.entry(origin.site).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Returns the distinct threads on which the live references of the family were created.
+    ///
+    /// Both strong and weak references are taken into account, each by the thread of its own
+    /// origin (not that of any of its ancestors).
+    #[cfg(not(feature = "no_std"))]
+    pub fn threads(this: &Snarc<T>) -> HashSet<ThreadId> {
+        let maps = this.tracker.read_all();
+
+        maps.iter()
+            .flat_map(|map| map.strongs.values().chain(map.weaks.values()))
+            .map(|origin| origin.thread)
+            .collect()
+    }
+
+    /// Returns whether the live references of the family were created on more than one thread.
+    ///
+    /// See `Snarc::threads` for the threads themselves.
+    #[cfg(not(feature = "no_std"))]
+    pub fn is_shared_across_threads(this: &Snarc<T>) -> bool {
+        let maps = this.tracker.read_all();
+
+        let mut threads = maps
+            .iter()
+            .flat_map(|map| map.strongs.values().chain(map.weaks.values()))
+            .map(|origin| origin.thread);
+        let first = threads.next();
+        threads.any(|thread| Some(thread) != first)
+    }
+
+    /// Writes the same listing as `Dump` directly to `w`.
+    ///
+    /// Unlike formatting a `Dump`, no intermediate `String` is created.
+    #[cfg(not(feature = "no_std"))]
+    pub fn dump_to<W: io::Write>(this: &Snarc<T>, w: &mut W) -> io::Result<()> {
+        write_dump(this, DumpFilter::All, |args| w.write_fmt(args))
+    }
+
+    /// Panics with a full `Dump` of the family, unless this is the only reference to the value.
+    ///
+    /// Intended for tests, to ensure no references are left over. See `LeakGuard` for checking a
+    /// scope instead.
+    pub fn assert_unique(this: &Snarc<T>) {
+        assert_counts(this, 1, 0, "Snarc is not unique");
+    }
+
+    /// Returns the same listing as `Dump`, restricted to the references selected by `filter`.
+    pub fn dump_filtered(this: &Snarc<T>, filter: DumpFilter) -> String {
+        let mut output = String::new();
+        write_dump(this, filter, |args| {
+            fmt::Write::write_fmt(&mut output, args)
+        })
+        .expect("Writing to a String failed. This should never happen.");
+        output
+    }
+
+    /// Returns the origins of the reference and all of its siblings as JSON.
+    ///
+    /// The resulting object has a `strongs` and a `weaks` array, each containing serialized
+    /// `Origin`s ordered by ID.
+    #[cfg(feature = "serde")]
+    pub fn family_json(this: &Snarc<T>) -> String {
+        /// Serialization helper.
+        #[derive(Serialize)]
+        struct Family {
+            strongs: Vec<Origin>,
+            weaks: Vec<Origin>,
+        }
+
+        let (mut strongs, mut weaks) = Snarc::family(this);
+        strongs.sort();
+        weaks.sort();
+
+        serde_json::to_string(&Family { strongs, weaks })
+            .expect("Failed to serialize family. This is a bug.")
+    }
+
+    /// Returns the live references of the family as CSV, for analysis in a spreadsheet.
+    ///
+    /// After a header, there is one row per reference with the columns `id`, `kind` (as in `Dump`),
+    /// `site`, `parent_id` (the ID of the reference it was created from, empty for the
+    /// `OriginKind::New` one) and `is_weak` (`true` or `false`). Strong references come first, both
+    /// kinds ordered by ID. Fields are quoted as per RFC 4180 where necessary, e.g. annotated sites.
+    pub fn family_csv(this: &Snarc<T>) -> String {
+        /// Appends `field` to `output`, quoted if it contains a separator, quote or line break.
+        fn push_field(output: &mut String, field: &str) {
+            if field.contains([',', '"', '\n', '\r']) {
+                output.push('"');
+                output.push_str(&field.replace('"', "\"\""));
+                output.push('"');
+            } else {
+                output.push_str(field);
+            }
+        }
+
+        let (mut strongs, mut weaks) = Snarc::family(this);
+        strongs.sort();
+        weaks.sort();
+
+        let mut output = String::from("id,kind,site,parent_id,is_weak\n");
+        let rows = strongs
+            .iter()
+            .map(|origin| (origin, false))
+            .chain(weaks.iter().map(|origin| (origin, true)));
+        for (origin, weak) in rows {
+            output.push_str(&format!("{},{},", origin.id, origin.kind.name()));
+            push_field(&mut output, &origin.site.to_string());
+            output.push(',');
+            if let Some(parent) = origin.kind.parent() {
+                output.push_str(&parent.id.to_string());
+            }
+            output.push_str(&format!(",{}\n", weak));
+        }
+
+        output
+    }
+}
+
+impl<T, const N: usize> Snarc<[T; N]> {
+    /// Converts a `Snarc` of an array into one of a slice.
+    ///
+    /// See `Snarc::unsize_with`.
+    pub fn unsize_to_slice(this: Snarc<[T; N]>) -> Snarc<[T]> {
+        Snarc::unsize_with(this, |inner| inner as Arc<[T]>)
+    }
+}
+
+impl Snarc<dyn Any + Send + Sync> {
+    /// Attempts to downcast the `Snarc` to a concrete type.
+    ///
+    /// On success, the reference keeps its ID and origin. Otherwise, the same `Snarc` is returned
+    /// unchanged.
+    ///
+    /// See `std::sync::Arc::downcast` for details.
+    pub fn downcast<T: Any + Send + Sync>(
+        this: Snarc<dyn Any + Send + Sync>,
+    ) -> Result<Snarc<T>, Snarc<dyn Any + Send + Sync>> {
+        let (inner, tracker, id) = Snarc::into_parts(this);
+
+        match Arc::downcast(inner) {
+            Ok(inner) => Ok(Snarc { inner, tracker, id }),
+            Err(inner) => Err(Snarc { inner, tracker, id }),
+        }
+    }
+}
+
+impl<T: Clone> Snarc<T> {
+    /// Makes a mutable reference into the given Arc.
+    ///
+    /// If there are other `Snarc` pointers to the same value, the inner value is cloned into a new
+    /// allocation first. If only `Weak` pointers remain, it is moved there instead, and the weak
+    /// references can no longer be upgraded. Either way, the new allocation has a family of its
+    /// own, starting with a single `OriginKind::New` origin for `this` at the location of the
+    /// caller, while the previous family simply loses a member.
+    ///
+    /// See `std::sync::Arc::make_mut` for details.
+    #[track_caller]
+    pub fn make_mut(this: &mut Snarc<T>) -> &mut T {
+        let before = Arc::as_ptr(&this.inner);
+        Arc::make_mut(&mut this.inner);
+
+        if !ptr::eq(before, Arc::as_ptr(&this.inner)) {
+            // Leave the previous family, as if `this` was dropped, and start a new one.
+            this.tracker
+                .map
+                .write()
+                .remove_strong(this.id)
+                .expect("Internal consistency error (make_mut)");
+
+            let (map, id) = Map::seeded(implicit(Site::caller()));
+            this.tracker = Tracker::new(map, id);
+            this.id = id;
+        }
+
+        Arc::get_mut(&mut this.inner)
+            .expect("Internal consistency error (make_mut). This should never happen.")
+    }
+}
+
+impl<T: ?Sized> Deref for Snarc<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        #[cfg(feature = "track-access")]
+        self.tracker.accesses.fetch_add(1, Ordering::Relaxed);
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for Snarc<T> {
+    fn drop(&mut self) {
+        // Even if another thread panicked while holding the lock, the family must be cleaned up.
+        let mut map = self.tracker.shard(self.id).write_recovering();
+        if map.remove_strong(self.id).is_none() {
+            inconsistent(map, "drop", self.id);
+        }
+    }
+}
+
+// Moving a `Snarc` never moves the value, so it is `Unpin` even if the value is not, like `Arc`.
+impl<T: ?Sized> Unpin for Snarc<T> {}
+
+impl<T: ?Sized> Clone for Snarc<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        self.clone_at_site(implicit(Site::caller()))
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Snarc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut debug = f.debug_struct("Snarc");
+        debug.field("id", &self.id).field("value", &&*self.inner);
+        if alternate {
+            debug.field("origin", &format_args!("{}", Snarc::origin(self)));
+        }
+        debug.finish()
+    }
+}
+
+impl<T: ?Sized> borrow::Borrow<T> for Snarc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Snarc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl AsRef<[u8]> for Snarc<str> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<T> From<T> for Snarc<T> {
+    fn from(data: T) -> Self {
+        Snarc::new_at_site(data, implicit(Site::Unknown))
+    }
+}
+
+impl<'a> From<&'a str> for Snarc<str> {
+    fn from(s: &'a str) -> Self {
+        Snarc::from_arc_at_site(Arc::from(s), implicit(Site::Unknown))
+    }
+}
+
+impl From<String> for Snarc<str> {
+    fn from(s: String) -> Self {
+        Snarc::from_arc_at_site(Arc::from(s), implicit(Site::Unknown))
+    }
+}
+
+impl<'a, T: Clone> From<&'a [T]> for Snarc<[T]> {
+    fn from(s: &'a [T]) -> Self {
+        Snarc::from_arc_at_site(Arc::from(s), implicit(Site::Unknown))
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Snarc<T> {
+    fn from(b: Box<T>) -> Self {
+        Snarc::from_arc_at_site(Arc::from(b), implicit(Site::Unknown))
+    }
+}
+
+/// Adopts an existing `Arc`, without copying the value.
+///
+/// The new `Snarc` is the first member of a new family, with an `OriginKind::New` origin. Other
+/// `Arc`s to the same value are not tracked, but still count as strong or weak references, and
+/// converting several of them results in independent families for the same value.
+impl<T: ?Sized> From<Arc<T>> for Snarc<T> {
+    fn from(inner: Arc<T>) -> Self {
+        Snarc::from_arc_at_site(inner, implicit(Site::Unknown))
+    }
+}
+
+impl<T> From<Vec<T>> for Snarc<[T]> {
+    fn from(v: Vec<T>) -> Self {
+        Snarc::from_arc_at_site(Arc::from(v), implicit(Site::Unknown))
+    }
+}
+
+/// Builder for a `Snarc` combining several of the options of its constructors.
+///
+/// ```rust
+/// use snarc::SnarcBuilder;
+///
+/// let foo = SnarcBuilder::new()
+///     .capacity(64)
+///     .annotate("pool")
+///     .sample(10)
+///     .with_history(100)
+///     .build(123);
+/// assert_eq!(*foo, 123);
+/// ```
+///
+/// Unless annotated, the location of the caller of `build` is recorded as the origin.
+#[derive(Debug)]
+pub struct SnarcBuilder {
+    /// Initial capacity, see `Snarc::with_capacity`.
+    capacity: usize,
+    /// Site of the new `Snarc`, if not the caller.
+    site: Option<Site>,
+    /// Sampling rate, see `Snarc::new_sampled`.
+    sample_rate: usize,
+    /// Number of dropped origins retained, if any, see `Snarc::new_with_bounded_history`.
+    history_limit: Option<usize>,
+    /// Receiver of lifecycle events, see `Snarc::new_with_hook`.
+    hook: Option<Box<dyn RefHook>>,
+}
+
+impl SnarcBuilder {
+    /// Creates a new builder, producing the same `Snarc` as `Snarc::new` unless configured.
+    pub fn new() -> SnarcBuilder {
+        SnarcBuilder {
+            capacity: DEFAULT_CAPACITY,
+            site: None,
+            sample_rate: 1,
+            history_limit: None,
+            hook: None,
+        }
+    }
+
+    /// Sets the number of strong and weak references each to make room for, see
+    /// `Snarc::with_capacity`.
+    pub fn capacity(mut self, capacity: usize) -> SnarcBuilder {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Labels the new `Snarc`, see `Snarc::new_annotated`.
+    pub fn annotate<S: Into<String>>(mut self, label: S) -> SnarcBuilder {
+        self.site = Some(Site::Annotated(label.into()));
+        self
+    }
+
+    /// Only records the site of every `rate`-th derived reference, see `Snarc::new_sampled`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    #[track_caller]
+    pub fn sample(mut self, rate: usize) -> SnarcBuilder {
+        assert!(rate > 0, "Sampling rate must not be zero.");
+        self.sample_rate = rate;
+        self
+    }
+
+    /// Retains the origins of the `limit` most recently dropped references, see
+    /// `Snarc::new_with_bounded_history`.
+    ///
+    /// Pass `usize::MAX` to retain all of them, like `Snarc::new_with_history`.
+    pub fn with_history(mut self, limit: usize) -> SnarcBuilder {
+        self.history_limit = Some(limit);
+        self
+    }
+
+    /// Reports the creation and drop of every reference to `hook`, see `Snarc::new_with_hook`.
+    pub fn hook<H: RefHook + 'static>(mut self, hook: H) -> SnarcBuilder {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Creates the configured `Snarc` for `data`.
+    #[track_caller]
+    pub fn build<T>(self, data: T) -> Snarc<T> {
+        let mut map = Map::new(self.capacity);
+        map.sample_rate = self.sample_rate;
+        if let Some(limit) = self.history_limit {
+            map.history = Some(VecDeque::with_capacity(limit.min(DEFAULT_CAPACITY)));
+            map.history_limit = limit;
+        }
+        map.hook = self.hook;
+
+        let site = match self.site {
+            Some(site) => site,
+            None => implicit(Site::caller()),
+        };
+        let id = map.seed(site);
+        Snarc::with_map(data, map, id)
+    }
+}
+
+impl Default for SnarcBuilder {
+    fn default() -> SnarcBuilder {
+        SnarcBuilder::new()
+    }
+}
+
+impl<T> Weak<T> {
+    /// Constructs a new `Weak`, without allocating any memory.
+    ///
+    /// The resulting `Weak` is not tracked and calling `upgrade` on it always returns `None`.
+    ///
+    /// See `std::sync::Weak::new` for details.
+    pub fn new() -> Weak<T> {
+        Weak {
+            id: None,
+            tracker: None,
+            inner: ArcWeak::new(),
+        }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Returns the tracker and ID of this reference, unless it is untracked.
+    fn tracking(&self) -> Option<(&Arc<Tracker>, Uid)> {
+        Some((self.tracker.as_ref()?, self.id?))
+    }
+
+    /// Internal upgrade function.
+    ///
+    /// Directly accepts a `Site` instance, creates the correct `Origin` with
+    /// `OriginKind::Upgraded`.
+    pub fn upgrade_at_site(&self, site: Site) -> Option<Snarc<T>> {
+        let (tracker, id) = self.tracking()?;
+
+        let inner = match self.inner.upgrade() {
+            Some(inner) => inner,
+            None => {
+                *tracker
+                    .shard(id)
+                    .write()
+                    .failed_upgrades
+                    .entry(id)
+                    .or_insert(0) += 1;
+                return None;
+            }
+        };
+
+        let (mut map, prev_origin, new_id) = tracker.derive(id, true, "upgrade");
+        let site = map.sample(site);
+        let new_origin = Origin::new(new_id, site, OriginKind::Upgraded(Arc::new(prev_origin)));
+        map.insert_strong(new_origin);
+
+        Some(Snarc {
+            inner,
+            tracker: tracker.clone(),
+            id: new_id,
+        })
+    }
+
+    /// Internal cloning function.
+    ///
+    /// Directly accepts a `Site` instance, creates the correct `Origin` with
+    /// `OriginKind::Cloned`. Since the tracker outlives the value, this works for dead references
+    /// as well.
+    fn clone_at_site(&self, site: Site) -> Weak<T> {
+        let (tracker, our_id) = match self.tracking() {
+            Some(tracking) => tracking,
+            // Untracked references produce untracked clones.
+            None => {
+                return Weak {
+                    id: None,
+                    tracker: None,
+                    inner: self.inner.clone(),
+                }
+            }
+        };
+
+        let (mut map, parent_origin, new_id) = tracker.derive(our_id, true, "weak clone");
+        let site = map.sample(site);
+        let new_origin = Origin::new(new_id, site, OriginKind::Cloned(Arc::new(parent_origin)));
+        map.insert_weak(new_origin);
+
+        Weak {
+            id: Some(new_id),
+            tracker: Some(tracker.clone()),
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc, extending the lifetime of the value if
+    /// successful.
+    ///
+    /// See `std::sync::Weak::upgrade` for details.
+    pub fn upgrade_at_line(&self, file: &'static str, line: u32) -> Option<Snarc<T>> {
+        self.upgrade_at_site(Site::SourceFile {
+            file,
+            line,
+            column: None,
+        })
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc, with the provided location as the origin of
+    /// the new `Snarc`, see `Snarc::new_at_location`.
+    pub fn upgrade_at_location(&self, location: &Location<'static>) -> Option<Snarc<T>> {
+        self.upgrade_at_site(Site::from(location))
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc, extending the lifetime of the value if
+    /// successful.
+    ///
+    /// The location of the caller is recorded as the origin of the new `Snarc`.
+    #[track_caller]
+    pub fn upgrade(&self) -> Option<Snarc<T>> {
+        self.upgrade_at_site(implicit(Site::caller()))
+    }
+
+    /// Upgrades the Weak pointer to an Arc, or calls `f` to create a new one if the value is gone.
+    ///
+    /// The file name and line are only recorded if the upgrade succeeds, the origin of a `Snarc`
+    /// returned by `f` is up to `f`.
+    pub fn upgrade_at_line_or_else<F>(&self, file: &'static str, line: u32, f: F) -> Snarc<T>
+    where
+        F: FnOnce() -> Snarc<T>,
+    {
+        self.upgrade_at_site(Site::SourceFile {
+            file,
+            line,
+            column: None,
+        })
+        .unwrap_or_else(f)
+    }
+
+    /// Upgrades the Weak pointer to an Arc, or calls `f` to create a new one if the value is gone.
+    ///
+    /// The location of the caller is recorded as the origin only if the upgrade succeeds, see
+    /// `Weak::upgrade_at_line_or_else`.
+    #[track_caller]
+    pub fn upgrade_or_else<F: FnOnce() -> Snarc<T>>(&self, f: F) -> Snarc<T> {
+        self.upgrade_at_site(implicit(Site::caller()))
+            .unwrap_or_else(f)
+    }
+
+    /// Returns true if the two `Weak`s point to the same allocation, or if both don't point to any
+    /// allocation.
+    ///
+    /// Tracking IDs are not taken into account. See `std::sync::Weak::ptr_eq` for details.
+    pub fn ptr_eq(this: &Weak<T>, other: &Weak<T>) -> bool {
+        this.inner.ptr_eq(&other.inner)
+    }
+
+    /// Returns a raw pointer to the value this `Weak` points to.
+    ///
+    /// The pointer is only valid while there are strong references. See `std::sync::Weak::as_ptr`
+    /// for details.
+    pub fn as_ptr(&self) -> *const T {
+        self.inner.as_ptr()
+    }
+
+    /// Returns the unique ID of this reference.
+    ///
+    /// Untracked `Weak`s, e.g. those created by `Weak::new`, have no ID.
+    pub fn id(&self) -> Option<Uid> {
+        self.id
+    }
+
+    /// Returns the origin of this reference, or `None` if the value is gone.
+    ///
+    /// Like `Snarc::origin`, a poisoned lock is ignored. Unlike upgrading, this creates no
+    /// reference. Untracked `Weak`s have no origin either.
+    pub fn origin(&self) -> Option<Origin> {
+        if self.inner.strong_count() == 0 {
+            return None;
+        }
+
+        self.recorded_origin()
+    }
+
+    /// Returns the origin of this reference, even if the value is gone.
+    ///
+    /// The tracking state outlives the value, so the origin remains known for as long as the
+    /// reference exists. Untracked `Weak`s have no origin.
+    fn recorded_origin(&self) -> Option<Origin> {
+        let (tracker, id) = self.tracking()?;
+        let map = tracker.shard(id).read();
+
+        Some(
+            map.weaks
+                .get(&id)
+                .expect("Internal consistency error (weak origin). This is a bug.")
+                .clone(),
+        )
+    }
+
+    /// Returns the origins of all strong and weak references of the family, see `Snarc::family`,
+    /// or `None` if the value is gone.
+    ///
+    /// Like `Weak::origin`, this creates no reference. Untracked `Weak`s have no family either.
+    pub fn family(&self) -> Option<(Vec<Origin>, Vec<Origin>)> {
+        if self.inner.strong_count() == 0 {
+            return None;
+        }
+
+        self.recorded_family()
+    }
+
+    /// Returns the origins of all strong and weak references of the family, even if the value is
+    /// gone, see `Weak::recorded_origin`.
+    pub(crate) fn recorded_family(&self) -> Option<(Vec<Origin>, Vec<Origin>)> {
+        let (tracker, _) = self.tracking()?;
+        Some(tracker.family())
+    }
+
+    /// Returns the number of times upgrading this reference failed because the value was gone.
+    ///
+    /// Only this reference is taken into account, not its clones. Untracked `Weak`s always return
+    /// `0`.
+    pub fn failed_upgrade_count(&self) -> usize {
+        self.tracking().map_or(0, |(tracker, id)| {
+            tracker
+                .shard(id)
+                .read()
+                .failed_upgrades
+                .get(&id)
+                .cloned()
+                .unwrap_or(0)
+        })
+    }
+
+    /// Returns true if the value this `Weak` points to is gone, or if it never pointed to one.
+    ///
+    /// Unlike `upgrade`, this creates no reference.
+    pub fn is_expired(&self) -> bool {
+        self.inner.strong_count() == 0
+    }
+
+    /// Returns true if the value this `Weak` points to is still alive, see `Weak::is_expired`.
+    pub fn is_alive(&self) -> bool {
+        !self.is_expired()
+    }
+
+    /// Gets the number of `Snarc` pointers to the value this `Weak` points to.
+    ///
+    /// See `std::sync::Weak::strong_count` for details.
+    pub fn strong_count(&self) -> usize {
+        self.inner.strong_count()
+    }
+
+    /// Gets the number of `Weak` pointers to the value this `Weak` points to.
+    ///
+    /// See `std::sync::Weak::weak_count` for details.
+    pub fn weak_count(&self) -> usize {
+        self.inner.weak_count()
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
+    fn drop(&mut self) {
+        // Untracked weak references, e.g. ones created by `Weak::new`, have nothing to clean up.
+        let (tracker, our_id) = match self.tracking() {
+            Some(tracking) => tracking,
+            None => return,
+        };
+
+        let mut map = tracker.shard(our_id).write_recovering();
+        if map.remove_weak(our_id).is_none() {
+            inconsistent(map, "weak drop", our_id);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Upgrading the inner pointer directly creates no tracked reference.
+        let value = self.inner.upgrade();
+
+        let mut debug = f.debug_struct("Weak");
+        debug.field("id", &self.id).field("alive", &value.is_some());
+        if let Some(ref value) = value {
+            debug.field("value", &&**value);
+        }
+        debug.finish()
+    }
+}
+
+impl<T: ?Sized> fmt::Display for Weak<T> {
+    /// Formats the origin chain of the reference, or `untracked` if there is none.
+    ///
+    /// Unlike `Weak::origin`, the origin is still shown after the value is gone.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.recorded_origin() {
+            Some(origin) => fmt::Display::fmt(&origin, f),
+            None => f.write_str("untracked"),
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        self.clone_at_site(implicit(Site::caller()))
+    }
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Weak::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clear_default_site_provider, set_default_site_provider, Snarc, SnarcBuilder, Weak,
+    };
+    #[cfg(not(feature = "no_std"))]
+    use scope;
+    use std::cell::Cell;
+    use std::panic::Location;
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    #[cfg(not(feature = "no_std"))]
+    use std::sync::Mutex;
+    use std::thread;
+    #[cfg(not(feature = "no_std"))]
+    use std::time::Duration;
+    use tracing::{KindCounts, Origin, OriginKind, Site, Uid};
+    use {Dot, Dump, DumpFilter, DumpWeak, RefHook};
+
+    #[test]
+    fn basic() {
+        let thing = ();
+        let thing_strong_0 = Snarc::new_at_line(thing, file!(), line!());
+        let thing_strong_1 = thing_strong_0.clone_at_line(file!(), line!());
+        let thing_weak_0 = Snarc::downgrade_at_line(&thing_strong_0, file!(), line!());
+        let thing_weak_1 = Snarc::downgrade_at_line(&thing_strong_0, file!(), line!());
+        let thing_strong_2 = thing_weak_0.upgrade_at_line(file!(), line!());
+
+        println!("\nthing_strong_0: {:?}", thing_strong_0);
+        println!("\nthing_strong_1: {:?}", thing_strong_1);
+        println!("\nthing_weak_0: {:?}", thing_weak_0);
+        println!("\nthing_weak_1: {:?}", thing_weak_1);
+        println!("\nthing_strong_2: {:?}", thing_strong_2);
+
+        // TODO: Actually check something.
+    }
+
+    #[test]
+    fn from_value() {
+        let s: Snarc<i32> = 5.into();
+
+        assert_eq!(*s, 5);
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+    }
+
+    #[test]
+    fn from_str() {
+        let s: Snarc<str> = "hello, world".into();
+
+        assert_eq!(&*s, "hello, world");
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+
+        let t = s.clone();
+        assert_eq!(&*t, "hello, world");
+    }
+
+    #[test]
+    fn from_string() {
+        let s: Snarc<str> = "hello, world".to_string().into();
+
+        assert_eq!(&*s, "hello, world");
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+    }
+
+    #[test]
+    fn from_vec() {
+        let s: Snarc<[i32]> = vec![1, 2, 3].into();
+        let t = s.clone_at_line("foo.rs", 1);
+        let w = Snarc::downgrade_at_line(&t, "foo.rs", 2);
+
+        assert_eq!(&*s, &[1, 2, 3]);
+
+        let (strongs, weaks) = Snarc::family(&s);
+        assert_eq!(strongs.len(), 2);
+        assert_eq!(weaks.len(), 1);
+
+        #[cfg(not(feature = "no_std"))]
+        assert_eq!(
+            format!(
+                "Family associated with ID: 0\n\
+                 S| new<0>[?]{{{0:?}}}\n\
+                 S| clone<1>[foo.rs:1]{{{0:?}}} <- new<0>[?]{{{0:?}}}\n\
+                 W| downgrade<2>[foo.rs:2]{{{0:?}}} <- clone<1>[foo.rs:1]{{{0:?}}} \
+                 <- new<0>[?]{{{0:?}}}\n",
+                thread::current().id()
+            ),
+            format!("{}", Dump(&s))
+        );
+
+        drop(w);
+    }
+
+    #[test]
+    fn from_slice() {
+        let items = ["a".to_string(), "b".to_string()];
+        let s: Snarc<[String]> = items[..].into();
+
+        assert_eq!(&*s, &items[..]);
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert!(format!("{}", Dump(&s)).contains("S| new<0>[?]"));
+    }
+
+    trait Speak {
+        fn speak(&self) -> String;
+    }
+
+    struct Dog {
+        name: String,
+    }
+
+    impl Speak for Dog {
+        fn speak(&self) -> String {
+            format!("{} says woof", self.name)
+        }
+    }
+
+    #[test]
+    fn from_arc() {
+        let arc = Arc::new(1);
+        let other = arc.clone();
+
+        let s: Snarc<i32> = arc.into();
+        assert_eq!(*s, 1);
+        assert_eq!(Snarc::id(&s), 0);
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(Snarc::origin(&s).site, Site::Unknown);
+
+        // The value is shared, not copied; only the `Snarc` is tracked.
+        assert!(ptr::eq(Snarc::as_ptr(&s), Arc::as_ptr(&other)));
+        assert_eq!(Snarc::strong_count(&s), 2);
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+
+        drop(other);
+        assert_eq!(Snarc::into_inner(s), Some(1));
+    }
+
+    #[test]
+    fn into_arc() {
+        let s = Snarc::new("data".to_string());
+        let arc: Arc<String> = Snarc::into_arc(s);
+        assert_eq!(Arc::try_unwrap(arc).unwrap(), "data");
+
+        let s = Snarc::new_with_history(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&s);
+        let arc = Snarc::into_arc(t);
+
+        assert!(ptr::eq(Snarc::as_ptr(&s), Arc::as_ptr(&arc)));
+        assert_eq!(Snarc::strong_count(&s), 2);
+        assert_eq!(Snarc::family(&s).0, vec![Snarc::origin(&s)]);
+        assert_eq!(Snarc::history(&s)[0].id, 1);
+
+        drop(s);
+        assert_eq!(*w.upgrade().unwrap(), 1);
+    }
+
+    #[test]
+    fn from_box_trait_object() {
+        let boxed: Box<dyn Speak> = Box::new(Dog {
+            name: "Rex".to_string(),
+        });
+        let s: Snarc<dyn Speak> = boxed.into();
+        let t = s.clone();
+
+        assert_eq!(s.speak(), "Rex says woof");
+        assert_eq!(t.speak(), "Rex says woof");
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+    }
+
+    #[test]
+    fn try_unwrap_unique() {
+        let s = Snarc::new_at_line("data".to_string(), file!(), line!());
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(Snarc::try_unwrap(s).unwrap(), "data");
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn try_unwrap_shared() {
+        let s = Snarc::new_at_line("data".to_string(), file!(), line!());
+        let t = s.clone();
+        let origin = Snarc::origin(&s).to_string();
+
+        let s = Snarc::try_unwrap(s).unwrap_err();
+        assert_eq!(Snarc::origin(&s).to_string(), origin);
+        assert_eq!(Snarc::strong_count(&s), 2);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+
+        drop(t);
+        assert_eq!(Snarc::try_unwrap(s).unwrap(), "data");
+    }
+
+    #[test]
+    fn make_mut_unique() {
+        let mut s = Snarc::new_at_line(vec![1, 2, 3], file!(), line!());
+        let origin = Snarc::origin(&s).to_string();
+
+        Snarc::make_mut(&mut s).push(4);
+
+        assert_eq!(*s, vec![1, 2, 3, 4]);
+        // No clone-on-write happened, so the reference is still the same.
+        assert_eq!(Snarc::origin(&s).to_string(), origin);
+    }
+
+    /// Appends `4` through `Snarc::make_mut`, returning the site of the call.
+    #[track_caller]
+    fn push_via_make_mut(s: &mut Snarc<Vec<i32>>) -> Site {
+        Snarc::make_mut(s).push(4);
+        Site::caller()
+    }
+
+    #[test]
+    fn make_mut_shared() {
+        let mut s = Snarc::new_at_line(vec![1, 2, 3], file!(), line!());
+        let t = s.clone_at_line(file!(), line!());
+
+        let site = push_via_make_mut(&mut s);
+
+        assert_eq!(*s, vec![1, 2, 3, 4]);
+        assert_eq!(*t, vec![1, 2, 3]);
+        assert!(!Snarc::ptr_eq(&s, &t));
+
+        // Both references now head their own families, the copy at the call of `make_mut`.
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(Snarc::origin(&s).site, site);
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert_eq!(Snarc::family(&t).0.len(), 1);
+    }
+
+    #[test]
+    fn make_mut_weak_only() {
+        let mut s = Snarc::new_at_line(vec![1, 2, 3], file!(), line!());
+        let w = Snarc::downgrade(&s);
+        let before: *const Vec<i32> = &*s;
+
+        let site = push_via_make_mut(&mut s);
+
+        // Moved rather than cloned, like `Arc::make_mut`, leaving the weak reference dead.
+        assert_eq!(*s, vec![1, 2, 3, 4]);
+        assert!(!ptr::eq(&*s, before));
+        assert!(w.upgrade().is_none());
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(Snarc::origin(&s).site, site);
+        assert_eq!(Snarc::family(&s), (vec![Snarc::origin(&s)], Vec::new()));
+    }
+
+    #[test]
+    fn into_inner_unique() {
+        let s = Snarc::new_at_line("data".to_string(), file!(), line!());
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(Snarc::into_inner(s), Some("data".to_string()));
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn into_inner_shared() {
+        let s = Snarc::new_at_line("data".to_string(), file!(), line!());
+        let t = s.clone_at_line(file!(), line!());
+        let w = Snarc::downgrade(&t);
+
+        assert_eq!(Snarc::into_inner(s), None);
+        assert_eq!(Snarc::strong_count(&t), 1);
+        assert_eq!(Snarc::family(&t).0, vec![Snarc::origin(&t)]);
+
+        assert_eq!(Snarc::into_inner(t), Some("data".to_string()));
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn dead_weak_clone_keeps_tracking() {
+        let s = Snarc::new_at_line(1, "main.rs", 1);
+        let w = Snarc::downgrade_at_line(&s, "main.rs", 2);
+        drop(s);
+
+        let v = w.clone_at_site(Site::SourceFile {
+            file: "main.rs",
+            line: 3,
+            column: None,
+        });
+        assert!(v.upgrade().is_none());
+
+        let id = v.id().expect("Clone of a dead weak reference is untracked");
+        assert_ne!(Some(id), w.id());
+
+        let origin = v
+            .tracker
+            .as_ref()
+            .unwrap()
+            .map
+            .read()
+            .weaks
+            .get(&id)
+            .cloned()
+            .unwrap();
+        let parent = match origin.kind {
+            OriginKind::Cloned(ref parent) => parent,
+            ref kind => panic!("Unexpected origin kind {:?}", kind),
+        };
+        assert_eq!(Some(parent.id), w.id());
+        match parent.kind {
+            OriginKind::Downgraded(ref root) => assert_eq!(root.id, 0),
+            ref kind => panic!("Unexpected origin kind {:?}", kind),
+        }
+
+        drop(w);
+        drop(v);
+    }
+
+    #[test]
+    fn new_cyclic() {
+        struct Node {
+            me: Weak<Node>,
+            value: i32,
+        }
+
+        let s = Snarc::new_cyclic(|me| {
+            assert!(me.upgrade().is_none());
+            Node {
+                me: me.clone(),
+                value: 1,
+            }
+        });
+
+        let t = s.me.upgrade().unwrap();
+        assert!(Snarc::ptr_eq(&s, &t));
+        assert_eq!(t.value, 1);
+
+        // Only the clone stored inside the value is left of the weak references.
+        let (_, weaks) = Snarc::family(&s);
+        assert_eq!(weaks.len(), 1);
+        let kinds: Vec<_> = weaks[0]
+            .ancestors()
+            .map(|origin| origin.kind.name())
+            .collect();
+        assert_eq!(kinds, vec!["clone", "downgrade", "new"]);
+        assert_eq!(Snarc::origin(&t).kind.parent().unwrap().id, weaks[0].id);
+    }
+
+    #[test]
+    fn unsize_with() {
+        let s = Snarc::new_at_line(
+            Dog {
+                name: "Rex".to_string(),
+            },
+            "foo.rs",
+            1,
+        );
+        let t = s.clone_at_line("foo.rs", 2);
+        let origin = Snarc::origin(&t).to_string();
+
+        let t = Snarc::unsize_with(t, |arc| arc as Arc<dyn Speak>);
+        assert_eq!(t.speak(), "Rex says woof");
+        assert_eq!(Snarc::origin(&t).to_string(), origin);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+
+        let slice = Snarc::unsize_to_slice(Snarc::new([1, 2, 3]));
+        assert_eq!(&*slice, &[1, 2, 3][..]);
+    }
+
+    #[test]
+    fn unsize_with_other_allocation() {
+        let s = Snarc::new_with_history(1);
+        let t = s.clone();
+
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            Snarc::unsize_with(t, |_| Arc::new(2) as Arc<dyn Send + Sync>)
+        }));
+        assert!(result.is_err());
+
+        // The consumed reference is accounted for as dropped.
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert_eq!(Snarc::history(&s).len(), 1);
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn implicit_coercion() {
+        let s: Snarc<[i32]> = Snarc::new_at_line([1, 2, 3], "foo.rs", 1);
+        let w = Snarc::downgrade(&Snarc::new([1, 2]));
+        let w: Weak<[i32]> = w;
+        let e = Snarc::new(());
+        let d: Snarc<dyn Send + Sync> = e.clone();
+
+        assert_eq!(s.len(), 3);
+        assert!(w.upgrade().is_none());
+        assert_eq!(Snarc::origin(&d).kind.parent().map(|p| p.id), Some(0));
+    }
+
+    #[test]
+    fn downcast() {
+        use std::any::Any;
+
+        let s = Snarc::new_at_line(1i32, "foo.rs", 1);
+        let t = s.clone_at_line("foo.rs", 2);
+        let origin = Snarc::origin(&t).to_string();
+
+        let any = Snarc::unsize_with(t, |arc| arc as Arc<dyn Any + Send + Sync>);
+        let any = Snarc::downcast::<String>(any).unwrap_err();
+        assert_eq!(Snarc::origin(&any).to_string(), origin);
+
+        let t = Snarc::downcast::<i32>(any).unwrap();
+        assert_eq!(*t, 1);
+        assert_eq!(Snarc::id(&t), 1);
+        assert_eq!(Snarc::origin(&t).to_string(), origin);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn scoped_sites() {
+        let label = |site: &Site| match *site {
+            Site::Annotated(ref label) => label.clone(),
+            ref site => panic!("Unexpected site {:?}", site),
+        };
+
+        let outside = Snarc::new(1);
+        let _outer = scope::push("handler");
+        let s = Snarc::new(1);
+        let t = outside.clone();
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(label(&Snarc::origin(&s).site), "handler");
+        assert_eq!(label(&Snarc::origin(&t).site), "handler");
+        assert!(Snarc::origin(&outside).site != Snarc::origin(&t).site);
+
+        {
+            let _inner = scope::push("db");
+            let u = w.upgrade().unwrap();
+            let v: Snarc<str> = "foo".into();
+            let x = s.clone_at_line("foo.rs", 1);
+
+            assert_eq!(label(&Snarc::origin(&u).site), "handler > db");
+            assert_eq!(label(&Snarc::origin(&v).site), "handler > db");
+            assert_eq!(
+                Snarc::origin(&x).site,
+                Site::SourceFile {
+                    file: "foo.rs",
+                    line: 1,
+                    column: None,
+                }
+            );
+        }
+
+        let (_, weaks) = Snarc::family(&s);
+        assert_eq!(label(&weaks[0].site), "handler");
+    }
+
+    #[test]
+    fn sampled() {
+        let s = Snarc::new_sampled(1, 4);
+        let clones: Vec<_> = (0..100).map(|_| s.clone()).collect();
+        let weak = Snarc::downgrade(&clones[0]);
+
+        // Every reference is still tracked, only the sites are left out.
+        assert_eq!(Snarc::strong_count(&s), 101);
+        assert_eq!(Snarc::family(&s).0.len(), 101);
+        let detailed = clones
+            .iter()
+            .filter(|clone| Snarc::origin(clone).site != Site::Unknown)
+            .count();
+        assert_eq!(detailed, 25);
+        assert_eq!(
+            Snarc::origin(&clones[0]).site,
+            Snarc::origin(&clones[4]).site
+        );
+        assert_eq!(Snarc::origin(&clones[1]).site, Site::Unknown);
+
+        // The origin of the value itself is always recorded.
+        assert_ne!(Snarc::origin(&s).site, Site::Unknown);
+        assert_eq!(
+            weak.upgrade().map(|u| Snarc::origin(&u).site),
+            Some(Site::Unknown)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Sampling rate must not be zero.")]
+    fn sampled_rate_zero() {
+        Snarc::new_sampled(1, 0);
+    }
+
+    #[test]
+    fn with_capacity() {
+        let s = Snarc::with_capacity(1, 1);
+        let clones: Vec<_> = (0..10).map(|_| s.clone()).collect();
+        let weaks: Vec<_> = clones.iter().map(Snarc::downgrade).collect();
+
+        assert_eq!(Snarc::family(&s).0.len(), 11);
+        assert_eq!(Snarc::family(&s).1.len(), 10);
+        assert_eq!(Snarc::origin(&s).kind, OriginKind::New);
+        assert_eq!(*weaks[9].upgrade().unwrap(), 1);
+
+        drop((clones, weaks));
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert!(Snarc::family(&s).1.is_empty());
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+        let before = Snarc::snapshot(&s);
+
+        let t = s.clone_at_line("foo.rs", 1);
+        drop(w);
+        drop(s.clone());
+        let after = Snarc::snapshot(&t);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added_strongs, vec![Snarc::origin(&t)]);
+        assert!(diff.removed_strongs.is_empty());
+        assert!(diff.added_weaks.is_empty());
+        assert_eq!(
+            diff.removed_weaks.iter().map(|o| o.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        assert_eq!(after.strongs().count(), 2);
+        assert!(after.diff(&after).is_empty());
+        assert_eq!(after.diff(&before).removed_strongs, diff.added_strongs);
+    }
+
+    #[test]
+    fn family_tree() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+        let u = w.upgrade().unwrap();
+        let v = s.clone();
+        drop(t);
+
+        let tree = Snarc::family_tree(&s);
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.root().map(|root| root.id), Some(0));
+
+        let children = |id| tree.children(id).map(|child| child.id).collect::<Vec<_>>();
+        assert_eq!(children(0), vec![1, Snarc::id(&v)]);
+        assert_eq!(children(1), vec![w.id().unwrap()]);
+        assert_eq!(children(2), vec![Snarc::id(&u)]);
+        assert!(children(4).is_empty());
+
+        // The dropped clone is kept as the ancestor of the others.
+        assert!(!tree.is_live(1));
+        assert!(tree.is_live(2));
+        assert_eq!(tree.get(1).map(|origin| origin.kind.name()), Some("clone"));
+
+        let order: Vec<_> = tree
+            .iter()
+            .map(|origin| (origin.id, origin.depth()))
+            .collect();
+        assert_eq!(order, vec![(0, 0), (1, 1), (2, 2), (3, 3), (4, 1)]);
+    }
+
+    #[test]
+    fn for_each_origin() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let u = t.clone();
+        let w = Snarc::downgrade(&u);
+        let v = w.clone();
+        drop(t);
+
+        let mut strongs = Vec::new();
+        Snarc::for_each_strong_origin(&s, |origin| strongs.push(origin.id));
+        let mut weaks = Vec::new();
+        Snarc::for_each_weak_origin(&s, |origin| weaks.push(origin.kind.name()));
+
+        let (family_strongs, family_weaks) = Snarc::family(&s);
+        assert_eq!(strongs.len(), family_strongs.len());
+        assert_eq!(weaks.len(), family_weaks.len());
+        strongs.sort();
+        weaks.sort();
+        assert_eq!(strongs, [0, 2]);
+        assert_eq!(weaks, ["clone", "downgrade"]);
+
+        drop((u, w, v));
+    }
+
+    #[test]
+    fn kind_counts() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let u = t.clone();
+        let w = Snarc::downgrade(&u);
+        let v = w.clone();
+        let x = v.upgrade().unwrap();
+        let y = x.clone();
+
+        assert_eq!(
+            Snarc::kind_counts(&s),
+            KindCounts {
+                new: 1,
+                cloned: 4,
+                upgraded: 1,
+                downgraded: 1,
+            }
+        );
+
+        drop((t, u, w, x));
+        let counts = Snarc::kind_counts(&y);
+        assert_eq!((counts.new, counts.cloned, counts.downgraded), (1, 2, 0));
+        assert_eq!(counts.upgraded, 0);
+
+        drop((v, y));
+    }
+
+    #[test]
+    fn upgrade_or_else() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let w = Snarc::downgrade(&s);
+
+        let t = w.upgrade_at_line_or_else("foo.rs", 2, || panic!("Fallback taken"));
+        let origin = Snarc::origin(&t);
+        assert_eq!(origin.kind.name(), "upgrade");
+        assert_eq!(
+            origin.site,
+            Site::SourceFile {
+                file: "foo.rs",
+                line: 2,
+                column: None,
+            }
+        );
+        assert!(Snarc::ptr_eq(&s, &t));
+        assert_eq!(w.upgrade_or_else(|| panic!("Fallback taken")).id, 3);
+
+        drop((s, t));
+        let u = w.upgrade_or_else(|| Snarc::new_at_line(2, "foo.rs", 3));
+        assert_eq!(*u, 2);
+        assert_eq!(Snarc::origin(&u).kind, OriginKind::New);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn failed_upgrade_count() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+        let v = w.clone();
+
+        drop(w.upgrade());
+        assert_eq!(w.failed_upgrade_count(), 0);
+
+        drop(s);
+        for _ in 0..3 {
+            assert!(w.upgrade().is_none());
+        }
+        assert!(v.upgrade_at_line("foo.rs", 1).is_none());
+
+        assert_eq!(w.failed_upgrade_count(), 3);
+        assert_eq!(v.failed_upgrade_count(), 1);
+        assert_eq!(w.clone().failed_upgrade_count(), 0);
+        assert_eq!(Weak::<i32>::new().failed_upgrade_count(), 0);
+    }
+
+    #[test]
+    fn weak_liveness() {
+        let s = Snarc::new_with_history(1);
+        let w = Snarc::downgrade(&s);
+
+        assert!(w.is_alive());
+        assert!(!w.is_expired());
+        // Peeking creates no references, so neither the family nor the history changes.
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert_eq!(Snarc::history(&s).len(), 0);
+        assert_eq!(Snarc::kind_counts(&s).upgraded, 0);
+
+        drop(s);
+        assert!(w.is_expired());
+        assert!(!w.is_alive());
+        assert!(Weak::<i32>::new().is_expired());
+    }
+
+    #[test]
+    fn dump_weak() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let w = Snarc::downgrade_at_line(&s, "foo.rs", 2);
+
+        let alive = DumpWeak(&w).to_string();
+        let mut lines = alive.lines();
+        assert_eq!(lines.next(), Some("Family associated with ID: 1"));
+        assert!(lines.next().unwrap().starts_with("S| new<0>[foo.rs:1]"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("W| downgrade<1>[foo.rs:2]"));
+        assert_eq!(lines.next(), None);
+
+        drop(s);
+        let dropped = DumpWeak(&w).to_string();
+        let mut lines = dropped.lines();
+        assert_eq!(lines.next(), Some("Family associated with ID: 1"));
+        assert_eq!(lines.next(), Some("Value dropped."));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("W(dead)| downgrade<1>[foo.rs:2]"));
+        assert_eq!(lines.next(), None);
+
+        assert_eq!(
+            DumpWeak(&Weak::<i32>::new()).to_string(),
+            "Untracked reference.\n"
+        );
+    }
+
+    #[test]
+    fn weak_new() {
+        let w: Weak<i32> = Weak::new();
+        assert!(w.upgrade().is_none());
+        assert!(w.upgrade_at_line(file!(), line!()).is_none());
+
+        let v = w.clone();
+        assert!(v.upgrade().is_none());
+
+        drop(w);
+        drop(v);
+
+        let d: Weak<i32> = Default::default();
+        assert!(d.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_ptr_eq() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w0 = Snarc::downgrade(&s);
+        let w1 = Snarc::downgrade(&t);
+
+        let other = Snarc::new(1);
+        let w2 = Snarc::downgrade(&other);
+
+        assert!(Weak::ptr_eq(&w0, &w1));
+        assert!(!Weak::ptr_eq(&w0, &w2));
+
+        let d0: Weak<i32> = Weak::new();
+        let d1: Weak<i32> = Weak::new();
+        assert!(Weak::ptr_eq(&d0, &d1));
+        assert!(!Weak::ptr_eq(&d0, &w0));
+    }
+
+    #[test]
+    fn is_ancestor_of() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let u = t.clone();
+        let v = Snarc::downgrade(&u).upgrade().unwrap();
+        let sibling = s.clone();
+
+        assert_eq!(Snarc::is_ancestor_of(&s, &t), Some(true));
+        assert_eq!(Snarc::is_ancestor_of(&s, &u), Some(true));
+        assert_eq!(Snarc::is_ancestor_of(&t, &v), Some(true));
+        assert_eq!(Snarc::is_ancestor_of(&u, &t), Some(false));
+        assert_eq!(Snarc::is_ancestor_of(&t, &sibling), Some(false));
+        assert_eq!(Snarc::is_ancestor_of(&t, &t), Some(false));
+
+        // Dropping an intermediate reference does not break the chain.
+        drop(t);
+        assert_eq!(Snarc::is_ancestor_of(&s, &v), Some(true));
+
+        let other = Snarc::new(1);
+        assert_eq!(Snarc::is_ancestor_of(&s, &other), None);
+        let rewrapped = Snarc::from(Snarc::into_arc(u));
+        assert_eq!(Snarc::is_ancestor_of(&s, &rewrapped), None);
+    }
+
+    #[test]
+    fn snarc_ptr_eq_weak() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+
+        let other = Snarc::new(1);
+        let v = Snarc::downgrade(&other);
+
+        assert!(Snarc::ptr_eq_weak(&s, &w));
+        assert!(!Snarc::ptr_eq_weak(&s, &v));
+        assert!(!Snarc::ptr_eq_weak(&s, &Weak::new()));
+        // No references were created by comparing.
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+        assert_eq!(w.failed_upgrade_count(), 0);
+    }
+
+    #[test]
+    fn weak_counts() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w0 = Snarc::downgrade(&s);
+        let w1 = w0.clone();
+
+        assert_eq!(w0.strong_count(), 2);
+        assert_eq!(w0.weak_count(), 2);
+
+        drop(s);
+        drop(t);
+
+        assert_eq!(w0.strong_count(), 0);
+        assert_eq!(w1.strong_count(), 0);
+        assert_eq!(w0.weak_count(), 0);
+
+        let d: Weak<i32> = Weak::new();
+        assert_eq!(d.strong_count(), 0);
+        assert_eq!(d.weak_count(), 0);
+    }
+
+    #[test]
+    fn ids() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+
+        let id = Snarc::id(&t);
+        assert_ne!(id, Snarc::id(&s));
+
+        let (strongs, weaks) = Snarc::family(&s);
+        assert!(strongs.iter().any(|origin| origin.id == id));
+        assert_eq!(weaks[0].id, w.id().unwrap());
+
+        assert_eq!(Weak::<i32>::new().id(), None);
+    }
+
+    #[test]
+    fn captures_caller() {
+        // The exact columns are not checked, see `captures_column`.
+        let here = |line| Site::SourceFile {
+            file: file!(),
+            line,
+            column: None,
+        };
+        let line_only = |site: Site| match site {
+            Site::SourceFile { file, line, column } => {
+                assert!(column.is_some());
+                Site::SourceFile {
+                    file,
+                    line,
+                    column: None,
+                }
+            }
+            other => other,
+        };
+
+        let (s, line) = (Snarc::new(1), line!());
+        assert_eq!(line_only(Snarc::origin(&s).site), here(line));
+
+        let (t, line) = (s.clone(), line!());
+        assert_eq!(line_only(Snarc::origin(&t).site), here(line));
+
+        let (w, line) = (Snarc::downgrade(&t), line!());
+        assert_eq!(line_only(Snarc::family(&s).1[0].site.clone()), here(line));
+
+        let (u, line) = (w.upgrade().unwrap(), line!());
+        assert_eq!(line_only(Snarc::origin(&u).site), here(line));
+
+        let (v, line) = (w.clone(), line!());
+        let weak_sites: Vec<_> = Snarc::family(&s)
+            .1
+            .into_iter()
+            .filter(|origin| Some(origin.id) == v.id())
+            .map(|origin| line_only(origin.site))
+            .collect();
+        assert_eq!(weak_sites, vec![here(line)]);
+    }
+
+    #[test]
+    fn weak_family() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+
+        let (mut strongs, weaks) = Snarc::family(&s);
+        assert_eq!(w.origin().as_ref(), weaks.first());
+        assert_eq!(w.origin().unwrap().kind.parent(), Some(&Snarc::origin(&t)));
+
+        let (mut w_strongs, w_weaks) = w.family().unwrap();
+        strongs.sort();
+        w_strongs.sort();
+        assert_eq!((w_strongs, w_weaks), (strongs, weaks));
+
+        // Inspecting records no upgrade.
+        assert_eq!(Snarc::kind_counts(&s).upgraded, 0);
+
+        drop(s);
+        assert!(w.family().is_some());
+
+        // Once the value is gone, there is nothing left to inspect.
+        drop(t);
+        assert!(w.origin().is_none());
+        assert!(w.family().is_none());
+        assert!(Weak::<i32>::new().origin().is_none());
+        assert!(Weak::<i32>::new().family().is_none());
+    }
+
+    #[test]
+    fn weak_formatting() {
+        let s = Snarc::new_at_line(5, "foo.rs", 1);
+        let w = Snarc::downgrade_at_line(&s, "foo.rs", 2);
+
+        assert_eq!(
+            format!("{:?}", w),
+            "Weak { id: Some(1), alive: true, value: 5 }"
+        );
+        assert_eq!(w.origin().map(|origin| origin.id), Some(1));
+        assert!(w.to_string().starts_with("downgrade<1>[foo.rs:2]"));
+        assert!(w.to_string().contains(" <- new<0>[foo.rs:1]"));
+
+        // Formatting creates no references.
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+
+        drop(s);
+        assert_eq!(format!("{:?}", w), "Weak { id: Some(1), alive: false }");
+        assert!(w.to_string().starts_with("downgrade<1>[foo.rs:2]"));
+
+        let untracked = Weak::<i32>::new();
+        assert_eq!(
+            format!("{:?}", untracked),
+            "Weak { id: None, alive: false }"
+        );
+        assert_eq!(untracked.to_string(), "untracked");
+        assert!(untracked.origin().is_none());
+    }
+
+    #[test]
+    fn debug() {
+        let s = Snarc::new_at_line(vec![1, 2], "foo.rs", 1);
+        let t = s.clone_at_line("foo.rs", 2);
+
+        assert_eq!(format!("{:?}", s), "Snarc { id: 0, value: [1, 2] }");
+        assert_eq!(format!("{:?}", t), "Snarc { id: 1, value: [1, 2] }");
+
+        let alternate = format!("{:#?}", t);
+        assert!(alternate.starts_with("Snarc {\n    id: 1,\n    value: [\n"));
+        assert!(alternate.contains("origin: clone<1>[foo.rs:2]"));
+        assert!(alternate.contains("<- new<0>[foo.rs:1]"));
+    }
+
+    #[test]
+    fn new_in_module() {
+        let s = Snarc::new_in_module(1, module_path!());
+
+        assert_eq!(
+            Snarc::origin(&s).site,
+            Site::Module("snarc::tracked::tests")
+        );
+        assert_eq!(
+            Snarc::origin(&s).site.to_string(),
+            "mod:snarc::tracked::tests"
+        );
+    }
+
+    #[test]
+    fn captures_column() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let (a, b) = (s.clone(), s.clone());
+
+        let column = |snarc: &Snarc<i32>| match Snarc::origin(snarc).site {
+            Site::SourceFile { line, column, .. } => (line, column),
+            ref other => panic!("unexpected site: {:?}", other),
+        };
+        let ((a_line, a_column), (b_line, b_column)) = (column(&a), column(&b));
+        assert_eq!(a_line, b_line);
+        assert!(a_column.unwrap() < b_column.unwrap());
+        assert!(Snarc::origin(&a).site.to_string().ends_with(&format!(
+            ":{}:{}",
+            a_line,
+            a_column.unwrap()
+        )));
+
+        // Explicitly passed sites have no column.
+        assert_eq!(column(&s), (1, None));
+        assert_eq!(Snarc::origin(&s).site.to_string(), "foo.rs:1");
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn new_with_backtrace() {
+        let s = Snarc::new_with_backtrace(1);
+
+        match Snarc::origin(&s).site {
+            Site::Backtrace(_) => (),
+            ref other => panic!("unexpected site: {:?}", other),
+        }
+        assert!(!format!("{}", Snarc::origin(&s)).is_empty());
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn records_thread() {
+        let s = Snarc::new(1);
+        let main_thread = thread::current().id();
+
+        let t = {
+            let s = s.clone();
+            thread::spawn(move || (s.clone(), thread::current().id()))
+        };
+        let (t, spawned_thread) = t.join().unwrap();
+
+        assert_ne!(main_thread, spawned_thread);
+        assert_eq!(Snarc::origin(&s).thread, main_thread);
+        assert_eq!(Snarc::origin(&t).thread, spawned_thread);
+
+        match Snarc::origin(&t).kind {
+            OriginKind::Cloned(ref parent) => assert_eq!(parent.thread, main_thread),
+            ref other => panic!("unexpected kind: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        let s = Snarc::new(0u8);
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let s = s.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        let t = s.clone();
+                        let w = Snarc::downgrade(&t);
+                        let u = w.upgrade().unwrap();
+                        drop((t, w, u));
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let s = s.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        assert_eq!(Snarc::origin(&s).id, Snarc::id(&s));
+                        let (strongs, _) = Snarc::family(&s);
+                        assert!(strongs.iter().any(|origin| origin.id == Snarc::id(&s)));
+                        assert!(Snarc::dump_filtered(&s, DumpFilter::Strong).contains("S| "));
+                    }
+                })
+            })
+            .collect();
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        let (strongs, weaks) = Snarc::family(&s);
+        assert_eq!(strongs.len(), Snarc::strong_count(&s));
+        assert_eq!(weaks.len(), Snarc::weak_count(&s));
+        assert_eq!((strongs.len(), weaks.len()), (1, 0));
+    }
+
+    #[test]
+    fn concurrent_ids_are_unique() {
+        let s = Snarc::new(0u8);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let s = s.clone();
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|_| {
+                            let t = s.clone();
+                            let w = Snarc::downgrade(&t);
+                            (Snarc::id(&t), w.id().unwrap(), t, w)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let refs: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        let mut ids: Vec<Uid> = refs.iter().flat_map(|r| vec![r.0, r.1]).collect();
+        let total = ids.len();
+        ids.sort();
+        ids.dedup();
+
+        assert_eq!(ids.len(), total);
+        assert!(!ids.contains(&Snarc::id(&s)));
+        assert_eq!(Snarc::family(&s).1.len(), 800);
+    }
+
+    #[test]
+    fn long_chain_shares_ancestors() {
+        fn parent(origin: &Origin) -> &Arc<Origin> {
+            match origin.kind {
+                OriginKind::Cloned(ref parent) => parent,
+                ref other => panic!("unexpected kind: {:?}", other),
+            }
+        }
+
+        let mut chain = vec![Snarc::new(())];
+        for _ in 0..1000 {
+            let next = chain.last().unwrap().clone();
+            chain.push(next);
+        }
+
+        let last = Snarc::origin(&chain[1000]);
+        let before = Snarc::origin(&chain[999]);
+        assert_eq!(last.depth(), 1000);
+
+        // The parent of `before` was not copied when `last` was created, but is shared.
+        assert!(Arc::ptr_eq(parent(parent(&last)), parent(&before)));
+        assert!(Arc::ptr_eq(
+            parent(&before),
+            parent(&Snarc::origin(&chain[999]))
+        ));
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn records_creation_time() {
+        let s = Snarc::new(1);
+        thread::sleep(Duration::from_millis(10));
+        let t = s.clone();
+
+        let first = Snarc::origin(&s);
+        let second = Snarc::origin(&t);
+
+        assert!(second.created_at > first.created_at);
+        assert!(first.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn annotate() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+
+        Snarc::annotate(&t, "connection pool handle");
+
+        assert_eq!(
+            Snarc::origin(&t).site,
+            Site::Annotated("connection pool handle".to_string())
+        );
+        assert_ne!(Snarc::origin(&s).site, Snarc::origin(&t).site);
+        assert!(format!("{}", Dump(&s)).contains("[\"connection pool handle\"]"));
+    }
+
+    #[test]
+    fn default_site_provider() {
+        thread_local! {
+            /// Whether the current thread runs this test.
+            static ACTIVE: Cell<bool> = const { Cell::new(false) };
+        }
+
+        /// Provides a site on the thread of this test only, as tests run concurrently.
+        fn provider() -> Site {
+            if ACTIVE.with(Cell::get) {
+                Site::Annotated("task-X".to_string())
+            } else {
+                Site::Unknown
+            }
+        }
+
+        set_default_site_provider(provider);
+        ACTIVE.with(|active| active.set(true));
+        let task = Site::Annotated("task-X".to_string());
+
+        // Conversions and references left out by sampling get the provided site.
+        let s = Snarc::new_sampled(1, 2);
+        let t = s.clone();
+        let u = s.clone();
+        let v = Snarc::from(2);
+        assert!(matches!(Snarc::origin(&t).site, Site::SourceFile { .. }));
+        assert_eq!(Snarc::origin(&u).site, task);
+        assert_eq!(Snarc::origin(&v).site, task);
+
+        // Caller locations, explicit sites and scopes are not affected.
+        for origin in [&s, &s.clone()].iter().map(|r| Snarc::origin(r)) {
+            assert!(matches!(origin.site, Site::SourceFile { .. }));
+        }
+        let w = Snarc::downgrade(&v);
+        assert!(matches!(w.origin().unwrap().site, Site::SourceFile { .. }));
+        assert_eq!(
+            Snarc::origin(&v.clone_at_line("foo.rs", 1))
+                .site
+                .to_string(),
+            "foo.rs:1"
+        );
+        #[cfg(not(feature = "no_std"))]
+        {
+            let _guard = scope::push("scoped");
+            assert_eq!(
+                Snarc::origin(&Snarc::from(3)).site,
+                Site::Annotated("scoped".to_string())
+            );
+        }
+
+        // Falls back to `Site::Unknown` if the provider has nothing to offer, or once it is removed.
+        ACTIVE.with(|active| active.set(false));
+        assert_eq!(Snarc::origin(&Snarc::from(3)).site, Site::Unknown);
+        ACTIVE.with(|active| active.set(true));
+        clear_default_site_provider();
+        assert_eq!(Snarc::origin(&Snarc::from(3)).site, Site::Unknown);
+        assert_eq!(Snarc::origin(&s.clone()).site, Site::Unknown);
+    }
+
+    #[test]
+    fn at_location() {
+        let location = Location::caller();
+        let site = Site::SourceFile {
+            file: file!(),
+            line: location.line(),
+            column: Some(location.column()),
+        };
+
+        let s = Snarc::new_at_location(1, location);
+        let t = s.clone_at_location(location);
+        let w = Snarc::downgrade_at_location(&t, location);
+        let u = w.upgrade_at_location(location).unwrap();
+
+        for origin in [Snarc::origin(&s), Snarc::origin(&t), Snarc::origin(&u)] {
+            assert_eq!(origin.site, site);
+        }
+        assert_eq!(w.origin().unwrap().site, site);
+        assert_eq!(Snarc::origin(&u).kind.name(), "upgrade");
+    }
+
+    #[test]
+    fn clear_annotation() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let t = s.clone();
+
+        Snarc::annotate(&t, "focus");
+        let u = t.clone();
+        Snarc::clear_annotation(&t);
+
+        assert_eq!(Snarc::origin(&t).site, Site::Unknown);
+        assert!(!Dump(&s).to_string().contains("S| clone<1>[\"focus\"]"));
+        // Not a label, so kept.
+        Snarc::clear_annotation(&s);
+        assert_eq!(Snarc::origin(&s).site.to_string(), "foo.rs:1");
+        // Already recorded in the origin chain of `u`.
+        let parent = Snarc::origin(&u).kind.parent().cloned().unwrap();
+        assert_eq!(parent.site, Site::Annotated("focus".to_string()));
+    }
+
+    #[test]
+    fn annotated_constructors() {
+        let session_id = 42;
+        let s = Snarc::new_annotated(1, format!("session {}", session_id));
+        let t = s.clone_annotated("worker");
+
+        assert_eq!(
+            Snarc::origin(&s).site,
+            Site::Annotated("session 42".to_string())
+        );
+        assert_eq!(
+            Snarc::origin(&t).site,
+            Site::Annotated("worker".to_string())
+        );
+    }
+
+    #[test]
+    fn annotated_downgrade() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let w = Snarc::downgrade_annotated(&s, "cache entry");
+
+        let origin = w.origin().unwrap();
+        assert_eq!(origin.site, Site::Annotated("cache entry".to_string()));
+        assert_eq!(origin.kind.name(), "downgrade");
+        assert_eq!(origin.kind.parent().unwrap().id, Snarc::id(&s));
+        assert!(Dump(&s)
+            .to_string()
+            .contains("W| downgrade<1>[\"cache entry\"]"));
+
+        let v = Snarc::new_weak(&s, "foo.rs", 2);
+        let u = Snarc::clone_weak(&s, "foo.rs", 3);
+        for (weak, line) in [(&v, 2), (&u, 3)] {
+            let origin = weak.origin().unwrap();
+            assert_eq!(origin.kind.name(), "downgrade");
+            assert_eq!(
+                origin.site,
+                Site::SourceFile {
+                    file: "foo.rs",
+                    line,
+                    column: None
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn dot() {
+        let s = Snarc::new_annotated(1, "root");
+        let t = s.clone_at_line("foo.rs", 1);
+        let u = t.clone_at_line("foo.rs", 2);
+        let w = Snarc::downgrade_at_line(&u, "foo.rs", 3);
+        drop(t);
+
+        let dot = format!("{}", Dot(&s));
+        let lines: Vec<_> = dot.lines().collect();
+
+        assert_eq!(lines[0], "digraph family {");
+        assert_eq!(*lines.last().unwrap(), "}");
+
+        let nodes: Vec<_> = lines.iter().filter(|l| l.contains("[label=")).collect();
+        let edges: Vec<_> = lines.iter().filter(|l| l.contains(" -> ")).collect();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(edges.len(), 3);
+
+        assert!(nodes[0].contains("new<0>\\n\\\"root\\\"\""));
+        // The dropped clone is still part of the chain, but grayed out.
+        assert!(nodes[1].contains("color=gray"));
+        assert!(nodes[3].contains("style=dashed"));
+        assert_eq!(*edges[2], "    2 -> 3 [style=dashed];");
+
+        drop(w);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn family_json() {
+        use serde_json::{self, Value};
+
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let u = t.clone();
+        let w0 = Snarc::downgrade(&s);
+        let w1 = Snarc::downgrade(&u);
+        drop(t);
+
+        let json: Value = serde_json::from_str(&Snarc::family_json(&s)).unwrap();
+        let strongs = json["strongs"].as_array().unwrap();
+        let weaks = json["weaks"].as_array().unwrap();
+
+        assert_eq!(strongs.len(), 2);
+        assert_eq!(weaks.len(), 2);
+        assert_eq!(strongs[0]["id"], 0);
+        assert_eq!(strongs[1]["id"], 2);
+        assert_eq!(weaks[0]["id"], 3);
+        assert_eq!(weaks[1]["kind"]["Downgraded"]["id"], 2);
+
+        drop((w0, w1));
+    }
+
+    #[test]
+    fn family_csv() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let t = s.clone_annotated("pool, \"main\"");
+        let u = t.clone_at_line("foo.rs", 3);
+        let w = Snarc::downgrade_at_line(&u, "foo.rs", 4);
+        drop(t);
+
+        let csv = Snarc::family_csv(&s);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], "id,kind,site,parent_id,is_weak");
+        assert_eq!(rows[1], "0,new,foo.rs:1,,false");
+        assert_eq!(rows[2], "2,clone,foo.rs:3,1,false");
+        assert_eq!(rows[3], "3,downgrade,foo.rs:4,2,true");
+
+        let v = s.clone_annotated("pool, \"main\"");
+        let csv = Snarc::family_csv(&v);
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv.contains("\n4,clone,\"\"\"pool, \"\"main\"\"\"\"\",0,false\n"));
+
+        drop(w);
+    }
+
+    #[test]
+    fn dump_unsized() {
+        let s = Snarc::unsize_to_slice(Snarc::new_at_line([1, 2, 3], "foo.rs", 1));
+        let w = Snarc::downgrade_at_line(&s, "foo.rs", 2);
+
+        let dump = format!("{}", Dump(&s));
+        assert!(dump.contains("S| new<0>[foo.rs:1]"));
+        assert!(dump.contains("W| downgrade<1>[foo.rs:2]"));
+
+        let d = Snarc::unsize_with(Snarc::new(()), |arc| arc as Arc<dyn Send + Sync>);
+        assert!(format!("{}", Dump(&d)).starts_with("Family associated with ID: 0\n"));
+
+        drop(w);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn dump_tree() {
+        let s = Snarc::new_at_line(1, "foo.rs", 1);
+        let a = s.clone_at_line("foo.rs", 2);
+        let w = Snarc::downgrade_at_line(&a, "foo.rs", 3);
+        let b = s.clone_at_line("foo.rs", 4);
+        let c = a.clone_at_line("foo.rs", 5);
+        drop(s);
+
+        assert_eq!(
+            format!("{:#}", Dump(&b)),
+            format!(
+                "Family associated with ID: 3\n\
+                 -| new<0>[foo.rs:1]{{{0:?}}}\n\
+                 \x20 S| clone<1>[foo.rs:2]{{{0:?}}}\n\
+                 \x20   W| downgrade<2>[foo.rs:3]{{{0:?}}}\n\
+                 \x20   S| clone<4>[foo.rs:5]{{{0:?}}}\n\
+                 \x20 S| clone<3>[foo.rs:4]{{{0:?}}}\n",
+                thread::current().id()
+            )
+        );
+
+        drop((a, w, c));
+        assert_eq!(
+            format!("{:#}", Dump(&b)),
+            format!(
+                "Family associated with ID: 3\n\
+                 -| new<0>[foo.rs:1]{{{0:?}}}\n\
+                 \x20 S| clone<3>[foo.rs:4]{{{0:?}}}\n",
+                thread::current().id()
+            )
+        );
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn dump_to() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+
+        let mut buf = Vec::new();
+        Snarc::dump_to(&s, &mut buf).unwrap();
+
+        assert_eq!(buf, format!("{}", Dump(&s)).into_bytes());
+        assert_eq!(String::from_utf8(buf).unwrap().lines().count(), 4);
+
+        drop(w);
+    }
+
+    #[test]
+    fn dump_filtered() {
+        let s = Snarc::new_at_line(1, "root.rs", 1);
+        let t = s.clone_at_line("conn.rs", 2);
+        let u = s.clone_at_line("pool.rs", 3);
+        let w0 = Snarc::downgrade_at_line(&t, "conn.rs", 4);
+        let w1 = Snarc::downgrade_at_line(&u, "pool.rs", 5);
+
+        let all = Snarc::dump_filtered(&s, DumpFilter::All);
+        assert_eq!(all, format!("{}", Dump(&s)));
+
+        let ids = |filter| -> Vec<String> {
+            Snarc::dump_filtered(&s, filter)
+                .lines()
+                .skip(1)
+                .map(|line| line[..line.find('>').unwrap() + 1].to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            ids(DumpFilter::Strong),
+            ["S| new<0>", "S| clone<1>", "S| clone<2>"]
+        );
+        assert_eq!(
+            ids(DumpFilter::Weak),
+            ["W| downgrade<3>", "W| downgrade<4>"]
+        );
+        assert_eq!(
+            ids(DumpFilter::File("conn")),
+            ["S| clone<1>", "W| downgrade<3>"]
+        );
+        // Every reference descends from the one created in `root.rs`.
+        assert_eq!(ids(DumpFilter::File("root.rs")).len(), 5);
+        assert!(ids(DumpFilter::File("missing.rs")).is_empty());
+
+        assert!(Snarc::dump_filtered(&s, DumpFilter::Weak).starts_with("Family associated"));
+
+        drop((w0, w1));
+    }
+
+    #[cfg(any(feature = "no_std", feature = "ordered"))]
+    #[test]
+    fn family_ordered() {
+        let s = Snarc::new(1);
+        let mut refs: Vec<_> = (0..100).map(|_| s.clone()).collect();
+        let weaks: Vec<_> = refs.iter().map(Snarc::downgrade).collect();
+        refs.retain(|r| Snarc::id(r) % 3 != 0);
+
+        let (strongs, weaks_origins) = Snarc::family(&s);
+        let ids: Vec<_> = strongs.iter().map(|origin| origin.id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ids.len(), refs.len() + 1);
+        assert!(weaks_origins.windows(2).all(|pair| pair[0].id < pair[1].id));
+
+        drop(weaks);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn site_histogram() {
+        let s = Snarc::new_at_line(1, "root.rs", 1);
+        let (clones, line) = ((0..5).map(|_| s.clone()).collect::<Vec<_>>(), line!());
+        let weak = Snarc::downgrade_at_line(&s, "root.rs", 1);
+
+        let histogram = Snarc::site_histogram(&s);
+
+        assert_eq!(histogram.len(), 2);
+        let Site::SourceFile { column, .. } = Snarc::origin(&clones[0]).site else {
+            panic!("Clone without a source file site");
+        };
+        assert_eq!(
+            histogram[&Site::SourceFile {
+                file: file!(),
+                line,
+                column,
+            }],
+            clones.len()
+        );
+        assert_eq!(
+            histogram[&Site::SourceFile {
+                file: "root.rs",
+                line: 1,
+                column: None,
+            }],
+            2
+        );
+
+        drop(weak);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn threads() {
+        use std::thread;
+
+        let s = Snarc::new(1);
+        let t = s.clone();
+        assert_eq!(Snarc::threads(&s).len(), 1);
+
+        let u = thread::spawn(move || t.clone()).join().unwrap();
+        let threads = Snarc::threads(&s);
+        assert_eq!(threads.len(), 2);
+        assert!(threads.contains(&thread::current().id()));
+
+        // Only live references count.
+        drop(u);
+        assert_eq!(Snarc::threads(&s).len(), 1);
+    }
+
+    #[test]
+    fn history() {
+        let s = Snarc::new_with_history(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&s);
+        let (t_id, w_id) = (Snarc::id(&t), w.id().unwrap());
+
+        assert!(Snarc::history(&s).is_empty());
+
+        drop(t);
+        drop(w);
+
+        let history = Snarc::history(&s);
+        assert_eq!(
+            history.iter().map(|origin| origin.id).collect::<Vec<_>>(),
+            vec![t_id, w_id]
+        );
+        #[cfg(not(feature = "no_std"))]
+        {
+            assert!(history.iter().all(|origin| origin.dropped_at.is_some()));
+            assert!(history[0].dropped_at <= history[1].dropped_at);
+        }
+
+        let (strongs, weaks) = Snarc::family(&s);
+        assert!(strongs.iter().all(|origin| origin.id != t_id));
+        assert!(weaks.is_empty());
+    }
+
+    #[cfg(feature = "track-access")]
+    #[test]
+    fn access_count() {
+        let s = Snarc::new(vec![1, 2, 3]);
+        let t = s.clone();
+        assert_eq!(Snarc::access_count(&s), 0);
+
+        for _ in 0..5 {
+            assert_eq!(s[0], 1);
+        }
+        assert_eq!(t.len(), 3);
+
+        // All references of the family share the counter.
+        assert_eq!(Snarc::access_count(&s), 6);
+        assert_eq!(Snarc::access_count(&t), 6);
+    }
+
+    #[cfg(feature = "contention")]
+    #[test]
+    fn contention_count() {
+        let s = Snarc::new(1);
+        assert_eq!(Snarc::contention_count(&s), 0);
+
+        // Every thread fails to take the lock held here once before blocking.
+        let guard = s.tracker.map.write();
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..4).map(|_| scope.spawn(|| s.clone())).collect();
+            while Snarc::contention_count(&s) < 4 {
+                thread::yield_now();
+            }
+            drop(guard);
+            for handle in handles {
+                drop(handle.join().unwrap());
+            }
+        });
+        assert_eq!(Snarc::contention_count(&s), 4);
+
+        drop(s.clone());
+        assert_eq!(Snarc::contention_count(&s), 4);
+    }
+
+    #[cfg(feature = "sharded")]
+    #[test]
+    fn sharded() {
+        let s = Snarc::new_sharded(1, 3);
+        let clones: Vec<_> = (0..4).map(|_| s.clone()).collect();
+        let w = Snarc::downgrade(&clones[0]);
+        let u = w.upgrade().unwrap();
+
+        // IDs come from a single counter, and each one determines the shard of its reference.
+        let ids: Vec<_> = clones.iter().map(Snarc::id).collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+        assert_eq!((w.id(), Snarc::id(&u)), (Some(5), 6));
+        assert_eq!(s.tracker.shards().count(), 3);
+        assert_eq!(s.tracker.shard(4).read().strongs.len(), 2);
+
+        let (mut strongs, weaks) = Snarc::family(&s);
+        strongs.sort();
+        let ids: Vec<_> = strongs.iter().map(|origin| origin.id).collect();
+        assert_eq!(ids, [0, 1, 2, 3, 4, 6]);
+        assert_eq!(weaks, [w.origin().unwrap()]);
+        assert_eq!(Snarc::origin(&u).kind.parent().unwrap().id, 5);
+        assert_eq!(Snarc::kind_counts(&s).cloned, 4);
+        assert_eq!(Dump(&s).to_string().lines().count(), 8);
+
+        // Batches of clones are spread across the shards as well.
+        let batch = Snarc::clone_n(&s, 4, "foo.rs", 1);
+        let ids: Vec<_> = batch.iter().map(Snarc::id).collect();
+        assert_eq!(ids, [7, 8, 9, 10]);
+        assert_eq!(s.tracker.shard(7).read().strongs.len(), 4);
+        drop(batch);
+
+        drop(clones);
+        drop(u);
+        assert_eq!(Snarc::family(&s).0, [Snarc::origin(&s)]);
+        assert_eq!(Snarc::peak_strong_count(&s), 10);
+        assert_eq!(Snarc::peak_weak_count(&s), 1);
+
+        // Adding up the peaks of the shards over-counts if not all of them are reached at once.
+        let t = Snarc::new_sharded(1, 2);
+        drop(t.clone());
+        let u = t.clone();
+        assert_eq!(Snarc::peak_strong_count(&t), 3);
+        drop((t, u));
+        drop(s);
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.failed_upgrade_count(), 1);
+    }
+
+    #[cfg(feature = "sharded")]
+    #[test]
+    fn sharded_lock_independence() {
+        let s = Snarc::new_sharded(1, 2);
+        let t = s.clone_at_line("foo.rs", 1);
+        let u = s.clone_at_line("foo.rs", 2);
+        assert_eq!((Snarc::id(&t), Snarc::id(&u)), (1, 2));
+
+        // With the first shard locked, as if by a busy thread, `t` can still be cloned into the
+        // second one. With a single lock, this would block until the guard is released.
+        let guard = s.tracker.map.write();
+        let v = thread::scope(|scope| scope.spawn(|| t.clone()).join().unwrap());
+        drop(guard);
+
+        assert_eq!(Snarc::id(&v), 3);
+        #[cfg(feature = "contention")]
+        assert_eq!(Snarc::contention_count(&s), 0);
+
+        // Dropping doesn't lock the other shards, either.
+        let guard = s.tracker.map.write();
+        thread::scope(|scope| scope.spawn(|| drop(v)).join().unwrap());
+        drop(guard);
+        assert_eq!(Snarc::family(&s).0.len(), 3);
+
+        drop((t, u));
+    }
+
+    #[test]
+    fn recycled_ids() {
+        let s = Snarc::new_with_recycled_ids(1);
+        let t = s.clone();
+        let u = s.clone();
+        assert_eq!((Snarc::id(&t), Snarc::id(&u)), (1, 2));
+
+        // The lowest free ID is reused first, for strong and weak references alike.
+        drop(u);
+        drop(t);
+        let w = Snarc::downgrade(&s);
+        assert_eq!(w.id(), Some(1));
+        let v = w.upgrade().unwrap();
+        assert_eq!(Snarc::id(&v), 2);
+        assert_eq!(Snarc::id(&s.clone()), 3);
+
+        // Lineage survives the reuse of an ancestor's ID.
+        drop(w);
+        let x = v.clone();
+        assert_eq!(Snarc::id(&x), 1);
+        let chain: Vec<_> = Snarc::origin(&x).ancestors().map(|o| o.id).collect();
+        assert_eq!(chain, vec![1, 2, 1, 0]);
+
+        // Batches of clones reuse free IDs first, too.
+        drop(x);
+        let batch = Snarc::clone_n(&v, 2, "foo.rs", 1);
+        let ids: Vec<_> = batch.iter().map(Snarc::id).collect();
+        assert_eq!(ids, [1, 3]);
+
+        // IDs keep increasing by default.
+        let s = Snarc::new(1);
+        drop(s.clone());
+        assert_eq!(Snarc::id(&s.clone()), 2);
+    }
+
+    #[test]
+    fn builder() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let s = SnarcBuilder::new()
+            .capacity(16)
+            .annotate("built")
+            .sample(2)
+            .with_history(1)
+            .hook(CountingHook {
+                created: created.clone(),
+                ..Default::default()
+            })
+            .build(1);
+
+        #[cfg(not(any(feature = "no_std", feature = "ordered")))]
+        assert!(s.tracker.map.read().strongs.capacity() >= 16);
+        assert_eq!(Snarc::origin(&s).site, Site::Annotated("built".to_owned()));
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        // Only every second derived reference records its site.
+        let t = s.clone_at_line("foo.rs", 1);
+        let u = s.clone_at_line("foo.rs", 2);
+        assert_eq!(Snarc::origin(&t).site.to_string(), "foo.rs:1");
+        assert_eq!(Snarc::origin(&u).site, Site::Unknown);
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+
+        // Only the most recently dropped origin is retained.
+        drop(t);
+        drop(u);
+        let history = Snarc::history(&s);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, 2);
+
+        // Unless configured otherwise, the builder matches `Snarc::new`.
+        let line = line!() + 1;
+        let v = SnarcBuilder::default().build(2);
+        assert!(Snarc::history(&v).is_empty());
+        assert!(Snarc::origin(&v)
+            .site
+            .to_string()
+            .starts_with(&format!("{}:{}:", file!(), line)));
+    }
+
+    #[test]
+    fn origins_by_depth() {
+        let s = Snarc::new(0);
+        let a = s.clone();
+        let a1 = a.clone();
+        let a11 = a1.clone();
+        let b = s.clone();
+        let w = Snarc::downgrade(&b);
+        let b1 = w.upgrade().unwrap();
+        let c = s.clone();
+
+        // Dropping an intermediate reference does not change the depth of its descendants. Upgrades
+        // count the downgrade they went through, placing `b1` as deep as `a11`.
+        drop(a1);
+
+        let ids: Vec<_> = Snarc::origins_by_depth(&a11)
+            .iter()
+            .map(|origin| (origin.depth(), origin.id))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                (0, Snarc::id(&s)),
+                (1, Snarc::id(&a)),
+                (1, Snarc::id(&b)),
+                (1, Snarc::id(&c)),
+                (3, Snarc::id(&a11)),
+                (3, Snarc::id(&b1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn origins_from_file() {
+        let s = Snarc::new_at_line(0, "src/connection.rs", 1);
+        let a = s.clone_at_line("src/connection.rs", 2);
+        let b = a.clone_at_line("src/pool.rs", 3);
+        let w = Snarc::downgrade_at_line(&b, "src/connection.rs", 4);
+        let c = b.clone_annotated("connection.rs");
+
+        let mut ids: Vec<_> = Snarc::origins_from_file(&s, "connection.rs")
+            .iter()
+            .map(|origin| origin.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![Snarc::id(&s), Snarc::id(&a), w.id().unwrap()]);
+
+        let pool = Snarc::origins_from_file(&c, "src/pool.rs");
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool[0].id, Snarc::id(&b));
+        assert!(Snarc::origins_from_file(&c, "missing.rs").is_empty());
+    }
+
+    #[test]
+    fn clone_n() {
+        let s = Snarc::new(1);
+        let clones = Snarc::clone_n(&s, 5, "foo.rs", 7);
+        assert_eq!(clones.len(), 5);
+        assert_eq!(Snarc::strong_count(&s), 6);
+
+        let mut ids: Vec<Uid> = clones.iter().map(Snarc::id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+
+        for c in &clones {
+            assert!(Snarc::ptr_eq(c, &s));
+            let origin = Snarc::origin(c);
+            assert_eq!(origin.site.to_string(), "foo.rs:7");
+            assert_eq!(origin.kind.parent().map(|p| p.id), Some(Snarc::id(&s)));
+        }
+
+        drop(clones);
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+        assert!(Snarc::clone_n(&s, 0, "foo.rs", 8).is_empty());
+    }
+
+    #[test]
+    fn clone_into() {
+        let s = Snarc::new(1);
+        let mut clones = vec![s.clone()];
+
+        Snarc::clone_into(&s, &mut clones, 10, "foo.rs", 9);
+        assert_eq!(clones.len(), 11);
+        assert_eq!(Snarc::strong_count(&s), 12);
+
+        // The existing element is kept in front, the new ones are distinct.
+        let mut ids: Vec<Uid> = clones.iter().map(Snarc::id).collect();
+        assert_eq!(ids[0], 1);
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids, (1..12).collect::<Vec<_>>());
+        for c in &clones[1..] {
+            assert_eq!(Snarc::origin(c).site.to_string(), "foo.rs:9");
+        }
+
+        clones.truncate(1);
+        Snarc::clone_into(&s, &mut clones, 0, "foo.rs", 10);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+    }
+
+    #[test]
+    fn id_space_exhausted() {
+        let s = Snarc::new(1);
+        s.tracker.next_id.store(Uid::MAX - 2, Ordering::Relaxed);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+        assert_eq!((Snarc::id(&t), w.id()), (Uid::MAX - 2, Some(Uid::MAX - 1)));
+
+        let err =
+            ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| s.clone())).unwrap_err();
+        let message = err.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("Snarc ID space exhausted."));
+
+        // IDs are allocated before locking, so the family remains usable.
+        assert_eq!(Snarc::strong_count(&s), 2);
+        drop((s, t, w));
+    }
+
+    #[test]
+    fn id_space_exhausted_with_recycling() {
+        let s = Snarc::new_with_recycled_ids(1);
+        s.tracker.next_id.store(Uid::MAX - 1, Ordering::Relaxed);
+        let t = s.clone();
+        assert_eq!(Snarc::id(&t), Uid::MAX - 1);
+
+        // Freed IDs can still be handed out once the counter is used up.
+        drop(t);
+        assert_eq!(Snarc::id(&s.clone()), Uid::MAX - 1);
+    }
+
+    #[cfg(feature = "u64-ids")]
+    #[test]
+    fn u64_ids() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+
+        let ids: (u64, Option<u64>) = (Snarc::id(&t), w.id());
+        assert_eq!(ids, (1, Some(2)));
+        assert_eq!(Snarc::origin(&t).id, 1u64);
+
+        s.tracker
+            .next_id
+            .store(u64::from(u32::MAX) + 1, Ordering::Relaxed);
+        assert_eq!(Snarc::id(&s.clone()), 1 << 32);
+    }
+
+    #[test]
+    fn peak_counts() {
+        let s = Snarc::new(1);
+        assert_eq!(Snarc::peak_strong_count(&s), 1);
+        assert_eq!(Snarc::peak_weak_count(&s), 0);
+
+        let clones: Vec<_> = (0..5).map(|_| s.clone()).collect();
+        let weaks: Vec<_> = clones.iter().take(2).map(Snarc::downgrade).collect();
+        drop(clones);
+        drop(weaks);
+
+        assert_eq!(Snarc::strong_count(&s), 1);
+        assert_eq!(Snarc::peak_strong_count(&s), 6);
+        assert_eq!(Snarc::peak_weak_count(&s), 2);
+
+        // Lower counts later on do not change the peak.
+        drop(s.clone());
+        assert_eq!(Snarc::peak_strong_count(&s), 6);
+    }
+
+    #[test]
+    fn bounded_history() {
+        let s = Snarc::new_with_bounded_history(1, 3);
+        let clones: Vec<_> = (0..5).map(|_| s.clone()).collect();
+        let ids: Vec<_> = clones.iter().map(Snarc::id).collect();
+        drop(clones);
+
+        let history = Snarc::history(&s);
+        assert_eq!(
+            history.iter().map(|origin| origin.id).collect::<Vec<_>>(),
+            ids[2..]
+        );
+
+        // Without room, nothing is retained.
+        let s = Snarc::new_with_bounded_history(1, 0);
+        drop(s.clone());
+        assert!(Snarc::history(&s).is_empty());
+    }
+
+    #[test]
+    fn no_history_by_default() {
+        let s = Snarc::new(1);
+        drop(s.clone());
+
+        assert!(Snarc::history(&s).is_empty());
+    }
+
+    #[derive(Default)]
+    struct CountingHook {
+        created: Arc<AtomicUsize>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl RefHook for CountingHook {
+        fn on_create(&self, _origin: &Origin) {
+            self.created.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_drop(&self, _id: Uid) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn hook() {
+        let hook = CountingHook::default();
+        let (created, dropped) = (hook.created.clone(), hook.dropped.clone());
+        let counts = || {
+            (
+                created.load(Ordering::SeqCst),
+                dropped.load(Ordering::SeqCst),
+            )
+        };
+
+        let s = Snarc::new_with_hook(1, hook);
+        assert_eq!(counts(), (1, 0));
+
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+        let v = w.clone();
+        let u = w.upgrade().unwrap();
+        assert_eq!(counts(), (5, 0));
+
+        drop((t, w, v, u));
+        assert_eq!(counts(), (5, 4));
+
+        drop(s);
+        assert_eq!(counts(), (5, 5));
+    }
+
+    #[test]
+    #[cfg(feature = "no_std")]
+    fn without_std() {
+        let a = Snarc::new_with_history(1);
+        let b = a.clone_annotated("b");
+        let w = Snarc::downgrade_at_line(&b, "foo.rs", 3);
+        let c = w.upgrade().unwrap();
+
+        let origin = Snarc::origin(&c).to_string();
+        assert!(origin.starts_with(&format!("upgrade<3>[{}:", file!())));
+        assert!(origin.contains(" <- downgrade<2>[foo.rs:3] <- clone<1>[\"b\"] <- new<0>["));
+
+        drop(b);
+        assert_eq!(Snarc::history(&a).len(), 1);
+        assert_eq!(Snarc::family(&a).0.len(), 2);
+        assert_eq!(
+            Snarc::dump_filtered(&a, DumpFilter::Weak).lines().count(),
+            2
+        );
+        assert!(Dump(&c).to_string().contains("W| downgrade<2>"));
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn shared_across_threads() {
+        /// Hook recording the IDs of all references reported as sharing the family.
+        #[derive(Default)]
+        struct SharingHook(Arc<Mutex<Vec<Uid>>>);
+
+        impl RefHook for SharingHook {
+            fn on_create(&self, _origin: &Origin) {}
+
+            fn on_drop(&self, _id: Uid) {}
+
+            fn on_shared(&self, origin: &Origin) {
+                self.0.lock().unwrap().push(origin.id);
+            }
+        }
+
+        let hook = SharingHook::default();
+        let shared = hook.0.clone();
+
+        let s = Snarc::new_with_hook(1, hook);
+        let w = Snarc::downgrade(&s);
+        assert!(!Snarc::is_shared_across_threads(&s));
+        assert!(shared.lock().unwrap().is_empty());
+
+        let t = s.clone();
+        let u = thread::spawn(move || w.upgrade().unwrap()).join().unwrap();
+        assert!(Snarc::is_shared_across_threads(&s));
+        assert_eq!(*shared.lock().unwrap(), vec![Snarc::id(&u)]);
+
+        drop(u);
+        assert!(!Snarc::is_shared_across_threads(&s));
+
+        // The hook only fires once per family.
+        let u = thread::spawn(move || t.clone()).join().unwrap();
+        assert!(Snarc::is_shared_across_threads(&s));
+        assert_eq!(shared.lock().unwrap().len(), 1);
+
+        drop(u);
+    }
+
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    #[test]
+    fn poisoned_map() {
+        /// Hook failing on the creation of any reference but the first.
+        struct PanickingHook;
+
+        impl RefHook for PanickingHook {
+            fn on_create(&self, origin: &Origin) {
+                assert_eq!(origin.id, 0, "Hook failure");
+            }
+
+            fn on_drop(&self, _id: Uid) {}
+        }
+
+        let s = Snarc::new_with_hook(1, PanickingHook);
+        thread::scope(|scope| assert!(scope.spawn(|| s.clone()).join().is_err()));
+
+        let origin = Snarc::try_origin(&s).unwrap_err().into_inner();
+        assert_eq!(origin.id, Snarc::id(&s));
+        assert_eq!(Snarc::origin(&s).to_string(), origin.to_string());
+
+        let (strongs, weaks) = Snarc::try_family(&s).unwrap_err().into_inner();
+        assert_eq!(strongs.len(), 1);
+        assert_eq!(strongs[0].to_string(), origin.to_string());
+        assert!(weaks.is_empty());
+        assert_eq!(Snarc::family(&s).0.len(), 1);
+
+        // Dropping recovers from the poisoned lock.
+        drop(s);
+    }
+
+    #[cfg(not(any(feature = "no_std", feature = "parking_lot")))]
+    #[test]
+    fn drop_poisoned_map() {
+        /// Hook failing on the creation of references annotated as doomed.
+        struct PanickingHook;
+
+        impl RefHook for PanickingHook {
+            fn on_create(&self, origin: &Origin) {
+                assert_ne!(
+                    origin.site,
+                    Site::Annotated("doomed".into()),
+                    "Hook failure"
+                );
+            }
+
+            fn on_drop(&self, _id: Uid) {}
+        }
+
+        let s = Snarc::new_with_hook(1, PanickingHook);
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+        thread::scope(|scope| assert!(scope.spawn(|| s.clone_annotated("doomed")).join().is_err()));
+        assert!(Snarc::try_origin(&s).is_err());
+
+        // Neither drop panics, and both still clean up after themselves.
+        drop(t);
+        drop(w);
+        let (strongs, weaks) = Snarc::family(&s);
+        assert_eq!(strongs.iter().map(|o| o.id).collect::<Vec<_>>(), [0]);
+        assert!(weaks.is_empty());
+
+        drop(s);
+    }
+
+    #[cfg(feature = "parking_lot")]
+    #[test]
+    fn panic_does_not_poison_map() {
+        /// Hook failing on the creation of the first derived reference only.
+        struct PanickingHook(AtomicUsize);
+
+        impl RefHook for PanickingHook {
+            fn on_create(&self, origin: &Origin) {
+                if origin.id != 0 {
+                    assert_ne!(self.0.fetch_add(1, Ordering::SeqCst), 0, "Hook failure");
+                }
+            }
+
+            fn on_drop(&self, _id: Uid) {}
+        }
+
+        let s = Snarc::new_with_hook(1, PanickingHook(AtomicUsize::new(0)));
+        thread::scope(|scope| assert!(scope.spawn(|| s.clone()).join().is_err()));
+
+        let origin = Snarc::try_origin(&s).unwrap();
+        assert_eq!(origin.to_string(), Snarc::origin(&s).to_string());
+        let (strongs, weaks) = Snarc::try_family(&s).unwrap();
+        assert_eq!((strongs.len(), weaks.len()), (1, 0));
+
+        // The family remains fully usable, including dropping.
+        let t = s.clone();
+        let w = Snarc::downgrade(&t);
+        assert_eq!(Snarc::family(&s).0.len(), 2);
+        drop(t);
+        assert_eq!(*w.upgrade().unwrap(), 1);
+        drop(s);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn consistency_error_message() {
+        let s = Snarc::new_with_history(1);
+        let t = s.clone_annotated("doomed");
+        let id = Snarc::id(&t);
+
+        // Remove the entry behind the back of `t`, as if it had already been dropped.
+        assert_eq!(s.tracker.map.write().remove_strong(id), Some(()));
+
+        let err =
+            ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| drop(t))).unwrap_err();
+        let message = err.downcast_ref::<String>().unwrap();
+
+        assert!(message.starts_with("Internal consistency error (drop): reference 1 (dropped: "));
+        assert!(message.contains(&Snarc::history(&s)[0].to_string()));
+        assert!(message.contains("clone<1>[\"doomed\"]"));
+    }
+}
@@ -0,0 +1,1157 @@
+//! Non-tracking implementation of `Snarc` and `Weak`, used if the `tracking` feature is disabled.
+//!
+//! Both types are plain wrappers around their `alloc::sync` counterparts that offer the same API as
+//! the tracking implementation, but record nothing. Sites passed in are ignored, every reference
+//! has the ID `0` and families are always empty.
+
+#[cfg(feature = "no_std")]
+use alloc::boxed::Box;
+#[cfg(feature = "no_std")]
+use alloc::string::String;
+use alloc::sync::{Arc, Weak as ArcWeak};
+#[cfg(feature = "no_std")]
+use alloc::vec::Vec;
+use core::any::Any;
+use core::borrow;
+use core::fmt;
+#[cfg(feature = "nightly")]
+use core::marker::Unsize;
+#[cfg(feature = "nightly")]
+use core::ops::CoerceUnsized;
+use core::ops::Deref;
+use core::panic::Location;
+use core::pin::Pin;
+#[cfg(not(feature = "no_std"))]
+use std::collections::{HashMap, HashSet};
+#[cfg(not(feature = "no_std"))]
+use std::io;
+#[cfg(not(feature = "no_std"))]
+use std::sync::PoisonError;
+#[cfg(not(feature = "no_std"))]
+use std::thread::ThreadId;
+#[cfg(not(feature = "no_std"))]
+use std::time::Duration;
+
+use tracing::{FamilySnapshot, FamilyTree, KindCounts, Origin, OriginKind, Site, Uid};
+#[cfg(not(feature = "no_std"))]
+use wait_for;
+use {assert_counts, write_dump, DumpFilter, RefHook, SharedError, SnarcRef};
+
+/// A 'snitching' atomically reference counted pointer that has been silenced.
+///
+/// Without the `tracking` feature, a `Snarc` is nothing more than an `Arc`. Like the tracking
+/// version, it is debug formatted with its ID and value, but without any origin.
+#[repr(transparent)]
+pub struct Snarc<T: ?Sized>(Arc<T>);
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Snarc<U>> for Snarc<T> {}
+
+/// The non-owned version of a `Snarc`.
+///
+/// Formatted like the tracking version, but without an ID or origin.
+#[repr(transparent)]
+pub struct Weak<T: ?Sized>(ArcWeak<T>);
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Weak<U>> for Weak<T> {}
+
+/// Does nothing, as no sites are recorded.
+pub fn set_default_site_provider(_provider: fn() -> Site) {}
+
+/// Does nothing, as no sites are recorded.
+pub fn clear_default_site_provider() {}
+
+impl<T> Snarc<T> {
+    /// Returns a new `Snarc`. The file name and line are ignored.
+    pub fn new_at_line(data: T, _file: &'static str, _line: u32) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Returns a new `Snarc`. The location is ignored.
+    pub fn new_at_location(data: T, _location: &Location<'static>) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Returns a new `Snarc`. The module path is ignored.
+    pub fn new_in_module(data: T, _module: &'static str) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Returns a new `Snarc`. The label is ignored.
+    pub fn new_annotated<S: Into<String>>(data: T, _label: S) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates new `Snarc`.
+    pub fn new(data: T) -> Snarc<T> {
+        Snarc(Arc::new(data))
+    }
+
+    /// Creates new `Snarc`. There are no sites to sample.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn new_sampled(data: T, rate: usize) -> Snarc<T> {
+        assert!(rate > 0, "Sampling rate must not be zero.");
+        Snarc(Arc::new(data))
+    }
+
+    /// Creates new `Snarc`. The capacity is ignored, as nothing is tracked.
+    pub fn with_capacity(data: T, _capacity: usize) -> Snarc<T> {
+        Snarc(Arc::new(data))
+    }
+
+    /// Creates a new pinned `Snarc`.
+    ///
+    /// See `std::sync::Arc::pin` for details.
+    pub fn pin(data: T) -> Pin<Snarc<T>> {
+        // Safe, as `Snarc` never moves its value, just like `Arc`.
+        unsafe { Pin::new_unchecked(Snarc::new(data)) }
+    }
+
+    /// Creates new `Snarc`. References are not assigned IDs to recycle.
+    pub fn new_with_recycled_ids(data: T) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates new `Snarc`. No history is retained.
+    pub fn new_with_history(data: T) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates new `Snarc`. No history is retained.
+    pub fn new_with_bounded_history(data: T, _limit: usize) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates new `Snarc`. The hook is dropped right away, as there are no events to report.
+    pub fn new_with_hook<H: RefHook + 'static>(data: T, _hook: H) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates new `Snarc`. There are no references to partition into shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    #[cfg(feature = "sharded")]
+    pub fn new_sharded(data: T, shards: usize) -> Snarc<T> {
+        assert!(shards > 0, "Number of shards must not be zero.");
+        Snarc(Arc::new(data))
+    }
+
+    /// Creates new `Snarc`. No backtrace is captured.
+    #[cfg(feature = "backtrace")]
+    pub fn new_with_backtrace(data: T) -> Snarc<T> {
+        Snarc::new(data)
+    }
+
+    /// Creates a new `Snarc` to a value that holds a `Weak` to itself.
+    ///
+    /// See `std::sync::Arc::new_cyclic` for details.
+    pub fn new_cyclic<F: FnOnce(&Weak<T>) -> T>(data_fn: F) -> Snarc<T> {
+        Snarc(Arc::new_cyclic(|inner| data_fn(&Weak(inner.clone()))))
+    }
+
+    /// Returns the contained value if the `Snarc` has exactly one strong reference.
+    ///
+    /// See `std::sync::Arc::try_unwrap` for details.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        Arc::try_unwrap(this.0).map_err(Snarc)
+    }
+
+    /// Returns the contained value once the `Snarc` is the only strong reference left, waiting at
+    /// most `timeout` for the others to be dropped.
+    ///
+    /// Polls the strong count, sleeping for increasingly longer in between, until it drops to one
+    /// or the timeout expires, then attempts `Snarc::try_unwrap`. A timeout too long to represent,
+    /// like `Duration::MAX`, waits indefinitely. Weak references do not prevent unwrapping, so they
+    /// are not waited for.
+    #[cfg(not(feature = "no_std"))]
+    pub fn try_unwrap_timeout(this: Self, timeout: Duration) -> Result<T, Self> {
+        wait_for(timeout, || Snarc::strong_count(&this) == 1);
+        Snarc::try_unwrap(this)
+    }
+
+    /// Returns the contained value if the `Snarc` is the last strong reference.
+    ///
+    /// See `std::sync::Arc::into_inner` for details.
+    pub fn into_inner(this: Self) -> Option<T> {
+        Arc::into_inner(this.0)
+    }
+}
+
+impl<T: ?Sized> Snarc<T> {
+    /// Clones `Snarc`. The file name and line are ignored.
+    pub fn clone_at_line(&self, _file: &'static str, _line: u32) -> Snarc<T> {
+        self.clone()
+    }
+
+    /// Clones `Snarc`. The location is ignored.
+    pub fn clone_at_location(&self, _location: &Location<'static>) -> Snarc<T> {
+        self.clone()
+    }
+
+    /// Clones `Snarc`. The label is ignored.
+    pub fn clone_annotated<S: Into<String>>(&self, _label: S) -> Snarc<T> {
+        self.clone()
+    }
+
+    /// Creates `k` clones of `this`. The file name and line are ignored.
+    pub fn clone_n(this: &Snarc<T>, k: usize, _file: &'static str, _line: u32) -> Vec<Snarc<T>> {
+        (0..k).map(|_| this.clone()).collect()
+    }
+
+    /// Appends `n` clones of `this` to `dest`. The file name and line are ignored.
+    pub fn clone_into(
+        this: &Snarc<T>,
+        dest: &mut Vec<Snarc<T>>,
+        n: usize,
+        _file: &'static str,
+        _line: u32,
+    ) {
+        dest.extend((0..n).map(|_| this.clone()));
+    }
+
+    /// Creates a new `Weak` pointer to this value. The file name and line are ignored.
+    pub fn downgrade_at_line(this: &Self, _file: &'static str, _line: u32) -> Weak<T> {
+        Snarc::downgrade(this)
+    }
+
+    /// Creates a new `Weak` pointer to this value. The location is ignored.
+    pub fn downgrade_at_location(this: &Self, _location: &Location<'static>) -> Weak<T> {
+        Snarc::downgrade(this)
+    }
+
+    /// Creates a new `Weak` pointer to this value.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        Weak(Arc::downgrade(&this.0))
+    }
+
+    /// Creates a new `Weak` pointer to this value. The label is ignored.
+    pub fn downgrade_annotated<S: Into<String>>(this: &Self, _label: S) -> Weak<T> {
+        Snarc::downgrade(this)
+    }
+
+    /// Creates a new `Weak` pointer to this value. The file name and line are ignored.
+    pub fn new_weak(this: &Self, _file: &'static str, _line: u32) -> Weak<T> {
+        Snarc::downgrade(this)
+    }
+
+    /// Creates a new `Weak` pointer to this value. The file name and line are ignored.
+    pub fn clone_weak(this: &Self, _file: &'static str, _line: u32) -> Weak<T> {
+        Snarc::downgrade(this)
+    }
+
+    /// Gets the number of `Weak` pointers to this value.
+    ///
+    /// See `std::sync::Arc::weak_count` for details.
+    pub fn weak_count(this: &Snarc<T>) -> usize {
+        Arc::weak_count(&this.0)
+    }
+
+    /// Gets the number of `Snarc` pointers to this value.
+    ///
+    /// See `std::sync::Arc::strong_count` for details.
+    pub fn strong_count(this: &Snarc<T>) -> usize {
+        Arc::strong_count(&this.0)
+    }
+
+    /// Returns `0`, as dereferences are not counted.
+    #[cfg(feature = "track-access")]
+    pub fn access_count(_this: &Snarc<T>) -> u64 {
+        0
+    }
+
+    /// Returns `0`, as there is no family to lock.
+    #[cfg(feature = "contention")]
+    pub fn contention_count(_this: &Snarc<T>) -> usize {
+        0
+    }
+
+    /// Returns the current number of strong references, peaks are not tracked.
+    pub fn peak_strong_count(this: &Snarc<T>) -> usize {
+        Arc::strong_count(&this.0)
+    }
+
+    /// Returns the current number of `Weak` pointers, peaks are not tracked.
+    pub fn peak_weak_count(this: &Snarc<T>) -> usize {
+        Arc::weak_count(&this.0)
+    }
+
+    /// Returns true if the two Arcs point to the same value (not just values that compare as equal).
+    ///
+    /// See `std::sync::Arc::ptr_eq` for details.
+    pub fn ptr_eq(this: &Snarc<T>, other: &Snarc<T>) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// Returns true if `weak` points to the same allocation as this reference.
+    pub fn ptr_eq_weak(this: &Snarc<T>, weak: &Weak<T>) -> bool {
+        Arc::as_ptr(&this.0) as *const u8 == weak.0.as_ptr() as *const u8
+    }
+
+    /// Returns `None`, references have no family.
+    pub fn is_ancestor_of(_ancestor: &Snarc<T>, _descendant: &Snarc<T>) -> Option<bool> {
+        None
+    }
+
+    /// Converts the `Snarc` into a plain `Arc` to the same value.
+    pub fn into_arc(this: Snarc<T>) -> Arc<T> {
+        this.0
+    }
+
+    /// Converts the `Snarc` into one of an unsized type, such as a slice or trait object.
+    ///
+    /// `f` must return the `Arc` passed to it, coerced to the target type, e.g. `|arc| arc as
+    /// Arc<dyn Trait>`. This allows the coercion on stable Rust, see the crate documentation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` returns an `Arc` pointing to a different allocation.
+    pub fn unsize_with<U, F>(this: Snarc<T>, f: F) -> Snarc<U>
+    where
+        U: ?Sized,
+        F: FnOnce(Arc<T>) -> Arc<U>,
+    {
+        let address = Arc::as_ptr(&this.0) as *const u8;
+        let inner = f(this.0);
+
+        assert!(
+            Arc::as_ptr(&inner) as *const u8 == address,
+            "Unsizing conversion returned a different allocation."
+        );
+
+        Snarc(inner)
+    }
+
+    /// Returns a reference to a part of the value, e.g. a field, selected by `f`.
+    ///
+    /// The returned `SnarcRef` keeps `this`, and with it the whole value, alive.
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(this: Snarc<T>, f: F) -> SnarcRef<T, U> {
+        SnarcRef::new(this, f)
+    }
+
+    /// Returns a raw pointer to the value.
+    ///
+    /// The pointer is valid as long as there are strong references and is the same for all of
+    /// them. See `std::sync::Arc::as_ptr` for details.
+    pub fn as_ptr(this: &Snarc<T>) -> *const T {
+        Arc::as_ptr(&this.0)
+    }
+
+    /// Returns true if this is the only reference to the value, strong or weak.
+    ///
+    /// This is exactly the condition under which `Snarc::get_mut` succeeds.
+    pub fn is_unique(this: &Snarc<T>) -> bool {
+        Snarc::strong_count(this) == 1 && Snarc::weak_count(this) == 0
+    }
+
+    /// Returns a mutable reference to the inner value, if there are no other Arc or Weak pointers
+    /// to the same value.
+    ///
+    /// See `std::sync::Arc::get_mut` for details.
+    pub fn get_mut(this: &mut Snarc<T>) -> Option<&mut T> {
+        Arc::get_mut(&mut this.0)
+    }
+
+    /// Like `get_mut`, but returns a `SharedError` holding the reference counts on failure.
+    ///
+    /// The `Dump` in the error lists no references, as there are no origins to show.
+    pub fn try_get_mut(this: &mut Snarc<T>) -> Result<&mut T, SharedError> {
+        if Arc::get_mut(&mut this.0).is_none() {
+            return Err(SharedError::new(this));
+        }
+
+        Ok(Arc::get_mut(&mut this.0).expect("Value became shared while borrowed. This is a bug."))
+    }
+
+    /// Returns a mutable reference to the inner value, if `this` is unique.
+    ///
+    /// The mutable counterpart of `Borrow::borrow`, same as `get_mut`. There is no `BorrowMut`
+    /// implementation, as its `borrow_mut` can not fail.
+    pub fn borrow_mut(this: &mut Snarc<T>) -> Option<&mut T> {
+        Snarc::get_mut(this)
+    }
+
+    /// Returns `0`, references are not assigned IDs.
+    pub fn id(_this: &Snarc<T>) -> Uid {
+        0
+    }
+
+    /// Does nothing, as there is no origin to label.
+    pub fn annotate<S: Into<String>>(_this: &Snarc<T>, _label: S) {}
+
+    /// Does nothing, as there is no origin to clear the label of.
+    pub fn clear_annotation(_this: &Snarc<T>) {}
+
+    /// Returns a placeholder origin with ID `0` and an unknown site.
+    pub fn origin(_this: &Snarc<T>) -> Origin {
+        Origin::new(0, Site::Unknown, OriginKind::New)
+    }
+
+    /// Returns a placeholder origin, see `Snarc::origin`. There is no lock to be poisoned.
+    #[cfg(not(feature = "no_std"))]
+    pub fn try_origin(this: &Snarc<T>) -> Result<Origin, PoisonError<Origin>> {
+        Ok(Snarc::origin(this))
+    }
+
+    /// Returns an empty family.
+    pub fn family(_this: &Snarc<T>) -> (Vec<Origin>, Vec<Origin>) {
+        (Vec::new(), Vec::new())
+    }
+
+    /// Never calls `f`, as there are no origins.
+    pub fn for_each_strong_origin<F: FnMut(&Origin)>(_this: &Snarc<T>, _f: F) {}
+
+    /// Never calls `f`, as there are no origins.
+    pub fn for_each_weak_origin<F: FnMut(&Origin)>(_this: &Snarc<T>, _f: F) {}
+
+    /// Returns an empty snapshot.
+    pub fn snapshot(_this: &Snarc<T>) -> FamilySnapshot {
+        FamilySnapshot::default()
+    }
+
+    /// Returns an empty tree.
+    pub fn family_tree(_this: &Snarc<T>) -> FamilyTree {
+        FamilyTree::default()
+    }
+
+    /// Returns no origins, as nothing is tracked.
+    pub fn origins_by_depth(_this: &Snarc<T>) -> Vec<Origin> {
+        Vec::new()
+    }
+
+    /// Returns no origins, as nothing is tracked.
+    pub fn origins_from_file(_this: &Snarc<T>, _file: &str) -> Vec<Origin> {
+        Vec::new()
+    }
+
+    /// Returns an empty family. There is no lock to be poisoned.
+    #[cfg(not(feature = "no_std"))]
+    #[allow(clippy::type_complexity)]
+    pub fn try_family(
+        this: &Snarc<T>,
+    ) -> Result<(Vec<Origin>, Vec<Origin>), PoisonError<(Vec<Origin>, Vec<Origin>)>> {
+        Ok(Snarc::family(this))
+    }
+
+    /// Returns an empty history.
+    pub fn history(_this: &Snarc<T>) -> Vec<Origin> {
+        Vec::new()
+    }
+
+    /// Returns all zero counts.
+    pub fn kind_counts(_this: &Snarc<T>) -> KindCounts {
+        KindCounts::default()
+    }
+
+    /// Returns `false`, threads are not recorded.
+    #[cfg(not(feature = "no_std"))]
+    pub fn is_shared_across_threads(_this: &Snarc<T>) -> bool {
+        false
+    }
+
+    /// Returns an empty set.
+    #[cfg(not(feature = "no_std"))]
+    pub fn threads(_this: &Snarc<T>) -> HashSet<ThreadId> {
+        HashSet::new()
+    }
+
+    /// Returns an empty histogram.
+    #[cfg(not(feature = "no_std"))]
+    pub fn site_histogram(_this: &Snarc<T>) -> HashMap<Site, usize> {
+        HashMap::new()
+    }
+
+    /// Writes the same listing as `Dump` directly to `w`.
+    #[cfg(not(feature = "no_std"))]
+    pub fn dump_to<W: io::Write>(this: &Snarc<T>, w: &mut W) -> io::Result<()> {
+        write_dump(this, DumpFilter::All, |args| w.write_fmt(args))
+    }
+
+    /// Panics unless this is the only reference to the value. The `Dump` included is empty.
+    pub fn assert_unique(this: &Snarc<T>) {
+        assert_counts(this, 1, 0, "Snarc is not unique");
+    }
+
+    /// Returns the same listing as `Dump`.
+    pub fn dump_filtered(this: &Snarc<T>, filter: DumpFilter) -> String {
+        let mut output = String::new();
+        write_dump(this, filter, |args| {
+            fmt::Write::write_fmt(&mut output, args)
+        })
+        .expect("Writing to a String failed. This should never happen.");
+        output
+    }
+
+    /// Returns an empty family as JSON.
+    #[cfg(feature = "serde")]
+    pub fn family_json(_this: &Snarc<T>) -> String {
+        String::from(r#"{"strongs":[],"weaks":[]}"#)
+    }
+
+    /// Returns an empty family as CSV, consisting of the header only.
+    pub fn family_csv(_this: &Snarc<T>) -> String {
+        String::from("id,kind,site,parent_id,is_weak\n")
+    }
+}
+
+impl<T, const N: usize> Snarc<[T; N]> {
+    /// Converts a `Snarc` of an array into one of a slice.
+    ///
+    /// See `Snarc::unsize_with`.
+    pub fn unsize_to_slice(this: Snarc<[T; N]>) -> Snarc<[T]> {
+        Snarc::unsize_with(this, |inner| inner as Arc<[T]>)
+    }
+}
+
+impl Snarc<dyn Any + Send + Sync> {
+    /// Attempts to downcast the `Snarc` to a concrete type.
+    ///
+    /// See `std::sync::Arc::downcast` for details.
+    pub fn downcast<T: Any + Send + Sync>(
+        this: Snarc<dyn Any + Send + Sync>,
+    ) -> Result<Snarc<T>, Snarc<dyn Any + Send + Sync>> {
+        Arc::downcast(this.0).map(Snarc).map_err(Snarc)
+    }
+}
+
+impl<T: Clone> Snarc<T> {
+    /// Makes a mutable reference into the given Arc.
+    ///
+    /// See `std::sync::Arc::make_mut` for details.
+    pub fn make_mut(this: &mut Snarc<T>) -> &mut T {
+        Arc::make_mut(&mut this.0)
+    }
+}
+
+impl<T: ?Sized> Deref for Snarc<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// Moving a `Snarc` never moves the value, so it is `Unpin` even if the value is not, like `Arc`.
+impl<T: ?Sized> Unpin for Snarc<T> {}
+
+impl<T: ?Sized> Clone for Snarc<T> {
+    fn clone(&self) -> Self {
+        Snarc(self.0.clone())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Snarc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Snarc")
+            .field("id", &0)
+            .field("value", &&*self.0)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> borrow::Borrow<T> for Snarc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Snarc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl AsRef<[u8]> for Snarc<str> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<T> From<T> for Snarc<T> {
+    fn from(data: T) -> Self {
+        Snarc::new(data)
+    }
+}
+
+impl<'a> From<&'a str> for Snarc<str> {
+    fn from(s: &'a str) -> Self {
+        Snarc(Arc::from(s))
+    }
+}
+
+impl From<String> for Snarc<str> {
+    fn from(s: String) -> Self {
+        Snarc(Arc::from(s))
+    }
+}
+
+impl<'a, T: Clone> From<&'a [T]> for Snarc<[T]> {
+    fn from(s: &'a [T]) -> Self {
+        Snarc(Arc::from(s))
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Snarc<T> {
+    fn from(b: Box<T>) -> Self {
+        Snarc(Arc::from(b))
+    }
+}
+
+/// Adopts an existing `Arc`, without copying the value.
+impl<T: ?Sized> From<Arc<T>> for Snarc<T> {
+    fn from(inner: Arc<T>) -> Self {
+        Snarc(inner)
+    }
+}
+
+impl<T> From<Vec<T>> for Snarc<[T]> {
+    fn from(v: Vec<T>) -> Self {
+        Snarc(Arc::from(v))
+    }
+}
+
+/// Builder for a `Snarc`, accepting the same options as the tracking version.
+///
+/// All options but the check of the sampling rate are ignored, as nothing is tracked.
+#[derive(Debug, Default)]
+pub struct SnarcBuilder;
+
+impl SnarcBuilder {
+    /// Creates a new builder.
+    pub fn new() -> SnarcBuilder {
+        SnarcBuilder
+    }
+
+    /// Does nothing, as there is no family to make room for.
+    pub fn capacity(self, _capacity: usize) -> SnarcBuilder {
+        self
+    }
+
+    /// Does nothing, as there is no origin to label.
+    pub fn annotate<S: Into<String>>(self, _label: S) -> SnarcBuilder {
+        self
+    }
+
+    /// Does nothing, as no sites are recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero, like the tracking version.
+    pub fn sample(self, rate: usize) -> SnarcBuilder {
+        assert!(rate > 0, "Sampling rate must not be zero.");
+        self
+    }
+
+    /// Does nothing, as no history is retained.
+    pub fn with_history(self, _limit: usize) -> SnarcBuilder {
+        self
+    }
+
+    /// Drops the hook right away, as there are no events to report.
+    pub fn hook<H: RefHook + 'static>(self, _hook: H) -> SnarcBuilder {
+        self
+    }
+
+    /// Creates a new `Snarc` for `data`.
+    pub fn build<T>(self, data: T) -> Snarc<T> {
+        Snarc::new(data)
+    }
+}
+
+impl<T> Weak<T> {
+    /// Constructs a new `Weak`, without allocating any memory.
+    ///
+    /// See `std::sync::Weak::new` for details.
+    pub fn new() -> Weak<T> {
+        Weak(ArcWeak::new())
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Attempts to upgrade the Weak pointer to an Arc. The site is ignored.
+    pub fn upgrade_at_site(&self, _site: Site) -> Option<Snarc<T>> {
+        self.upgrade()
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc. The file name and line are ignored.
+    pub fn upgrade_at_line(&self, _file: &'static str, _line: u32) -> Option<Snarc<T>> {
+        self.upgrade()
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc. The location is ignored.
+    pub fn upgrade_at_location(&self, _location: &Location<'static>) -> Option<Snarc<T>> {
+        self.upgrade()
+    }
+
+    /// Attempts to upgrade the Weak pointer to an Arc, extending the lifetime of the value if
+    /// successful.
+    ///
+    /// See `std::sync::Weak::upgrade` for details.
+    pub fn upgrade(&self) -> Option<Snarc<T>> {
+        self.0.upgrade().map(Snarc)
+    }
+
+    /// Upgrades the Weak pointer to an Arc, or calls `f` to create a new one if the value is gone.
+    /// The file name and line are ignored.
+    pub fn upgrade_at_line_or_else<F>(&self, _file: &'static str, _line: u32, f: F) -> Snarc<T>
+    where
+        F: FnOnce() -> Snarc<T>,
+    {
+        self.upgrade_or_else(f)
+    }
+
+    /// Upgrades the Weak pointer to an Arc, or calls `f` to create a new one if the value is gone.
+    pub fn upgrade_or_else<F: FnOnce() -> Snarc<T>>(&self, f: F) -> Snarc<T> {
+        self.upgrade().unwrap_or_else(f)
+    }
+
+    /// Returns true if the two `Weak`s point to the same allocation, or if both don't point to any
+    /// allocation.
+    ///
+    /// See `std::sync::Weak::ptr_eq` for details.
+    pub fn ptr_eq(this: &Weak<T>, other: &Weak<T>) -> bool {
+        this.0.ptr_eq(&other.0)
+    }
+
+    /// Returns a raw pointer to the value this `Weak` points to.
+    ///
+    /// The pointer is only valid while there are strong references. See `std::sync::Weak::as_ptr`
+    /// for details.
+    pub fn as_ptr(&self) -> *const T {
+        self.0.as_ptr()
+    }
+
+    /// Returns `None`, references are not assigned IDs.
+    pub fn id(&self) -> Option<Uid> {
+        None
+    }
+
+    /// Returns `None`, references have no origin.
+    pub fn origin(&self) -> Option<Origin> {
+        None
+    }
+
+    /// Returns `None`, references have no family.
+    pub fn family(&self) -> Option<(Vec<Origin>, Vec<Origin>)> {
+        None
+    }
+
+    /// Returns `None`, references have no family.
+    pub(crate) fn recorded_family(&self) -> Option<(Vec<Origin>, Vec<Origin>)> {
+        None
+    }
+
+    /// Returns `0`, failed upgrades are not counted.
+    pub fn failed_upgrade_count(&self) -> usize {
+        0
+    }
+
+    /// Returns true if the value this `Weak` points to is gone, or if it never pointed to one.
+    ///
+    /// Unlike `upgrade`, this creates no reference.
+    pub fn is_expired(&self) -> bool {
+        self.0.strong_count() == 0
+    }
+
+    /// Returns true if the value this `Weak` points to is still alive, see `Weak::is_expired`.
+    pub fn is_alive(&self) -> bool {
+        !self.is_expired()
+    }
+
+    /// Gets the number of `Snarc` pointers to the value this `Weak` points to.
+    ///
+    /// See `std::sync::Weak::strong_count` for details.
+    pub fn strong_count(&self) -> usize {
+        self.0.strong_count()
+    }
+
+    /// Gets the number of `Weak` pointers to the value this `Weak` points to.
+    ///
+    /// See `std::sync::Weak::weak_count` for details.
+    pub fn weak_count(&self) -> usize {
+        self.0.weak_count()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.0.upgrade();
+
+        let mut debug = f.debug_struct("Weak");
+        debug
+            .field("id", &None::<Uid>)
+            .field("alive", &value.is_some());
+        if let Some(ref value) = value {
+            debug.field("value", &&**value);
+        }
+        debug.finish()
+    }
+}
+
+impl<T: ?Sized> fmt::Display for Weak<T> {
+    /// Formats as `untracked`, as there is no origin.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("untracked")
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        Weak(self.0.clone())
+    }
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Weak::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snarc, SnarcBuilder, Weak};
+    use std::mem;
+    use std::panic::Location;
+    use std::sync::Arc;
+    use tracing::Site;
+    use {Dump, DumpFilter, DumpWeak};
+
+    #[test]
+    fn ids_and_sites_are_ignored() {
+        let a = Snarc::new_at_line(1, "foo.rs", 1);
+        let b = a.clone_at_line("foo.rs", 2);
+        let w = Snarc::downgrade(&b);
+        Snarc::annotate(&b, "ignored");
+
+        assert_eq!(Snarc::id(&b), 0);
+        assert_eq!(w.id(), None);
+        assert_eq!(Snarc::origin(&b).id, 0);
+        assert_eq!(Snarc::origin(&b).site, Site::Unknown);
+    }
+
+    #[test]
+    fn families_are_empty() {
+        let a = Snarc::new_at_line(1, "foo.rs", 1);
+        let _w: Weak<_> = Snarc::downgrade(&a);
+
+        assert_eq!(Snarc::family(&a), (Vec::new(), Vec::new()));
+        assert!(Snarc::history(&a).is_empty());
+        #[cfg(not(feature = "no_std"))]
+        assert!(Snarc::site_histogram(&a).is_empty());
+        assert_eq!(Dump(&a).to_string(), "Family associated with ID: 0\n");
+        assert_eq!(
+            Snarc::dump_filtered(&a, DumpFilter::Weak),
+            "Family associated with ID: 0\n"
+        );
+    }
+
+    #[test]
+    fn same_size_as_arc() {
+        assert_eq!(mem::size_of::<Snarc<u8>>(), mem::size_of::<Arc<u8>>());
+        assert_eq!(
+            mem::size_of::<Weak<u8>>(),
+            mem::size_of::<::std::sync::Weak<u8>>()
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_std"))]
+    fn try_queries_succeed() {
+        let a = Snarc::new(1);
+
+        assert_eq!(Snarc::try_origin(&a).unwrap().site, Site::Unknown);
+        assert_eq!(Snarc::try_family(&a).unwrap(), Snarc::family(&a));
+    }
+
+    #[test]
+    fn new_cyclic() {
+        let cyclic = Snarc::new_cyclic(|w: &Weak<u8>| {
+            assert!(w.upgrade().is_none());
+            3
+        });
+
+        assert_eq!(*cyclic, 3);
+        assert_eq!(Snarc::id(&cyclic), 0);
+    }
+
+    #[test]
+    fn downcast() {
+        use std::any::Any;
+
+        let any = Snarc::unsize_with(Snarc::new(4u8), |arc| arc as Arc<dyn Any + Send + Sync>);
+        let any = Snarc::downcast::<String>(any).unwrap_err();
+
+        assert_eq!(*Snarc::downcast::<u8>(any).unwrap(), 4);
+    }
+
+    #[test]
+    fn into_arc() {
+        let s = Snarc::new(1);
+        let t = s.clone();
+        let arc: Arc<i32> = Snarc::into_arc(t);
+
+        assert!(Arc::ptr_eq(&arc, &Snarc::into_arc(s)));
+        assert_eq!(*arc, 1);
+    }
+
+    #[test]
+    fn upgrade_or_else() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(*w.upgrade_or_else(|| panic!("Fallback taken")), 1);
+        assert_eq!(*Weak::new().upgrade_or_else(|| Snarc::new(2)), 2);
+    }
+
+    #[test]
+    fn weak_liveness() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+        assert!(w.is_alive());
+
+        drop(s);
+        assert!(w.is_expired());
+        assert!(Weak::<u8>::new().is_expired());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_std"))]
+    fn threads() {
+        let s = Snarc::new(1);
+
+        assert!(Snarc::threads(&s).is_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_std"))]
+    fn never_shared_across_threads() {
+        let s = Snarc::new(1);
+        let t = ::std::thread::spawn(move || s.clone()).join().unwrap();
+
+        assert!(!Snarc::is_shared_across_threads(&t));
+    }
+
+    #[test]
+    fn family_tree_is_empty() {
+        let s = Snarc::new(1);
+        let _t = s.clone();
+
+        assert!(Snarc::family_tree(&s).root().is_none());
+    }
+
+    #[test]
+    fn snapshots_are_empty() {
+        let s = Snarc::new(1);
+        let before = Snarc::snapshot(&s);
+        let _t = s.clone();
+
+        assert!(before.diff(&Snarc::snapshot(&s)).is_empty());
+    }
+
+    #[test]
+    fn with_capacity() {
+        let s = Snarc::with_capacity(5, 1);
+
+        assert_eq!(*s.clone(), 5);
+    }
+
+    #[test]
+    fn new_sampled() {
+        let s = Snarc::new_sampled(6, 2);
+
+        assert_eq!(*s.clone(), 6);
+        assert_eq!(Snarc::origin(&s.clone()).site, Site::Unknown);
+    }
+
+    #[test]
+    fn bounded_history_is_empty() {
+        let s = Snarc::new_with_bounded_history(1, 1);
+        drop(s.clone());
+
+        assert!(Snarc::history(&s).is_empty());
+    }
+
+    #[test]
+    fn peak_counts_are_current() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+        drop(s.clone());
+
+        assert_eq!(Snarc::peak_strong_count(&s), 1);
+        assert_eq!(Snarc::peak_weak_count(&s), 1);
+        drop(w);
+        assert_eq!(Snarc::peak_weak_count(&s), 0);
+    }
+
+    #[test]
+    fn debug_format() {
+        let s = Snarc::new(1);
+
+        assert_eq!(format!("{:?}", s), "Snarc { id: 0, value: 1 }");
+    }
+
+    #[test]
+    fn weak_formatting() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(
+            format!("{:?}", w),
+            "Weak { id: None, alive: true, value: 1 }"
+        );
+        assert_eq!(w.to_string(), "untracked");
+        assert!(w.origin().is_none());
+    }
+
+    #[test]
+    fn weak_family_is_none() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+
+        assert!(w.family().is_none());
+    }
+
+    #[test]
+    fn dump_weak() {
+        let s = Snarc::new(1);
+        let w = Snarc::downgrade(&s);
+
+        assert_eq!(DumpWeak(&w).to_string(), "Untracked reference.\n");
+    }
+
+    #[test]
+    #[cfg(feature = "track-access")]
+    fn access_count_is_zero() {
+        let s = Snarc::new(1);
+
+        assert_eq!((*s, Snarc::access_count(&s)), (1, 0));
+    }
+
+    #[test]
+    fn new_with_recycled_ids() {
+        let s = Snarc::new_with_recycled_ids(7);
+
+        assert_eq!(*s.clone(), 7);
+    }
+
+    #[test]
+    fn clone_n() {
+        let s = Snarc::new(8);
+        let clones = Snarc::clone_n(&s, 2, file!(), line!());
+
+        assert_eq!(clones.len(), 2);
+        assert_eq!(Snarc::strong_count(&s), 3);
+    }
+
+    #[test]
+    fn builder() {
+        let built = SnarcBuilder::new()
+            .capacity(2)
+            .annotate("built")
+            .sample(2)
+            .with_history(1)
+            .build(9);
+
+        assert_eq!(*built.clone(), 9);
+    }
+
+    #[test]
+    fn weak_constructors() {
+        let a = Snarc::new(1);
+        let weaks = [
+            Snarc::downgrade_annotated(&a, "weak"),
+            Snarc::new_weak(&a, file!(), line!()),
+            Snarc::clone_weak(&a, file!(), line!()),
+        ];
+
+        assert_eq!(Snarc::weak_count(&a), 3);
+        assert!(weaks.iter().all(|w| w.upgrade().is_some()));
+    }
+
+    #[test]
+    fn ptr_eq_weak() {
+        let a = Snarc::new(1);
+        let w = Snarc::downgrade(&a);
+
+        assert!(Snarc::ptr_eq_weak(&a, &w));
+        assert!(!Snarc::ptr_eq_weak(&Snarc::new(1), &w));
+    }
+
+    #[cfg(feature = "contention")]
+    #[test]
+    fn contention_count_is_zero() {
+        let s = Snarc::new(1);
+        drop(s.clone());
+
+        assert_eq!(Snarc::contention_count(&s), 0);
+    }
+
+    #[cfg(feature = "sharded")]
+    #[test]
+    fn new_sharded() {
+        let s = Snarc::new_sharded(1, 2);
+
+        assert_eq!(*s.clone(), 1);
+        assert!(Snarc::family(&s).0.is_empty());
+    }
+
+    #[test]
+    fn clone_into() {
+        let s = Snarc::new(8);
+        let mut clones = vec![s.clone()];
+        Snarc::clone_into(&s, &mut clones, 2, file!(), line!());
+
+        assert_eq!(clones.len(), 3);
+        assert_eq!(Snarc::strong_count(&s), 4);
+    }
+
+    #[test]
+    fn clear_annotation_is_ignored() {
+        let a = Snarc::new(1);
+        Snarc::annotate(&a, "ignored");
+        Snarc::clear_annotation(&a);
+
+        assert_eq!(Snarc::origin(&a).site, Site::Unknown);
+    }
+
+    #[test]
+    fn at_location() {
+        let location = Location::caller();
+        let s = Snarc::new_at_location(9, location).clone_at_location(location);
+        let w = Snarc::downgrade_at_location(&s, location);
+
+        assert_eq!(w.upgrade_at_location(location).as_deref(), Some(&9));
+    }
+
+    #[test]
+    fn default_site_provider_is_ignored() {
+        super::set_default_site_provider(|| Site::Annotated("ignored".to_string()));
+        let s = Snarc::from(1);
+        super::clear_default_site_provider();
+
+        assert_eq!(Snarc::origin(&s).site, Site::Unknown);
+    }
+
+    #[test]
+    fn for_each_origin_never_calls() {
+        let a = Snarc::new(1);
+        let _w = Snarc::downgrade(&a);
+
+        Snarc::for_each_strong_origin(&a, |_| panic!("Untracked origin"));
+        Snarc::for_each_weak_origin(&a, |_| panic!("Untracked origin"));
+    }
+
+    #[test]
+    fn is_ancestor_of_is_unknown() {
+        let a = Snarc::new(1);
+        let b = a.clone();
+
+        assert_eq!(Snarc::is_ancestor_of(&a, &b), None);
+    }
+}